@@ -0,0 +1,238 @@
+// Benchmarks of the render path, from loading a dataset to rendering many sources at once. Every
+// benchmark uses the synthetic spherical head dataset so no measured SOFA files are needed.
+// usage: cargo bench --features fixtures --bench render [-- FILTER]
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rand::prelude::*;
+use sofar::reader::{Filter, Sofar};
+use sofar::render::Renderer;
+
+use ase_project::fixtures::{self, TempSofa};
+use ase_project::hrtf_backend;
+use ase_project::ola::OlaConvolver;
+use ase_project::sofa_info;
+use ase_project::spatializer_efx::{
+    spherical_to_cartesian, ConvolutionEngine, SpatializerEfx, PARTITION_LEN,
+};
+
+const SAMPLE_RATE: f32 = 48000.0;
+// Lengths of typical measured datasets, e.g. 128 for CIPIC-like and 512 for KU100 HRIRs
+const FILTER_LENS: [usize; 3] = [128, 256, 512];
+// Filter length of the benchmarks that vary something else
+const FILTER_LEN: usize = 256;
+
+/// The synthetic dataset written to a temporary file, removed again when the benchmark is done
+/// with it.
+fn dataset(filter_len: usize) -> TempSofa {
+    let set = fixtures::spherical_head(SAMPLE_RATE as f64, filter_len);
+    TempSofa::new(&format!("bench-{filter_len}"), &set).unwrap()
+}
+
+fn open(filter_len: usize) -> Sofar {
+    sofa_info::open_sofa(dataset(filter_len).path(), SAMPLE_RATE).unwrap()
+}
+
+/// Random directions all around the listener, the same ones on every run.
+fn positions(count: usize) -> Vec<(f32, f32, f32)> {
+    let mut rng = StdRng::seed_from_u64(1);
+    (0..count)
+        .map(|_| {
+            let azimuth = rng.gen_range(-180.0..180.0);
+            let elevation = rng.gen_range(-40.0..90.0);
+            spherical_to_cartesian(azimuth, elevation, 1.0)
+        })
+        .collect()
+}
+
+fn noise(len: usize) -> Vec<f32> {
+    let mut rng = StdRng::seed_from_u64(2);
+    (0..len).map(|_| rng.gen_range(-1.0..1.0)).collect()
+}
+
+fn bench_load(c: &mut Criterion) {
+    let mut group = c.benchmark_group("load");
+    group.sample_size(20);
+
+    for filter_len in FILTER_LENS {
+        let file = dataset(filter_len);
+        let path = file.path();
+        // validation, libmysofa's loading and resampling
+        group.bench_with_input(BenchmarkId::new("open_sofa", filter_len), path, |b, path| {
+            b.iter(|| sofa_info::open_sofa(path, SAMPLE_RATE).unwrap())
+        });
+        // plus the renderers and the pre-delay scan of the dataset
+        group.bench_with_input(BenchmarkId::new("backend", filter_len), path, |b, path| {
+            b.iter(|| hrtf_backend::open(path, SAMPLE_RATE).unwrap())
+        });
+    }
+
+    group.finish();
+}
+
+fn bench_filter_lookup(c: &mut Criterion) {
+    let mut group = c.benchmark_group("filter_lookup");
+    let positions = positions(64);
+
+    for filter_len in FILTER_LENS {
+        let sofa = open(filter_len);
+        let mut filter = Filter::new(filter_len);
+
+        group.bench_function(BenchmarkId::new("interpolated", filter_len), |b| {
+            let mut next = positions.iter().cycle();
+            b.iter(|| {
+                let &(x, y, z) = next.next().unwrap();
+                sofa.filter(x, y, z, &mut filter);
+            })
+        });
+        group.bench_function(BenchmarkId::new("nearest", filter_len), |b| {
+            let mut next = positions.iter().cycle();
+            b.iter(|| {
+                let &(x, y, z) = next.next().unwrap();
+                sofa.filter_nointerp(x, y, z, &mut filter);
+            })
+        });
+    }
+
+    group.finish();
+}
+
+fn bench_set_filter(c: &mut Criterion) {
+    let mut group = c.benchmark_group("set_filter");
+
+    for filter_len in FILTER_LENS {
+        let sofa = open(filter_len);
+        let mut filter = Filter::new(filter_len);
+        sofa.filter(1.0, 0.0, 0.0, &mut filter);
+
+        let mut render = Renderer::builder(filter_len)
+            .with_sample_rate(SAMPLE_RATE)
+            .with_partition_len(PARTITION_LEN)
+            .build()
+            .unwrap();
+        group.bench_function(BenchmarkId::new("sofar", filter_len), |b| {
+            b.iter(|| render.set_filter(black_box(&filter)).unwrap())
+        });
+
+        let mut ola = OlaConvolver::new(filter_len, PARTITION_LEN);
+        group.bench_function(BenchmarkId::new("overlap_add", filter_len), |b| {
+            b.iter(|| ola.set_filter(black_box(&filter)))
+        });
+    }
+
+    group.finish();
+}
+
+/// The whole DSP core for a static source at different host buffer sizes.
+fn bench_block_size(c: &mut Criterion) {
+    let mut group = c.benchmark_group("block_size");
+    let input = noise(1024);
+
+    for engine in [ConvolutionEngine::Sofar, ConvolutionEngine::OverlapAdd] {
+        for block_len in [32, 64, 128, 256, 512, 1024] {
+            let mut efx = SpatializerEfx::new(open(FILTER_LEN), SAMPLE_RATE, block_len).unwrap();
+            efx.set_engine(engine);
+            efx.set_spherical_position(30.0, 10.0, 1.0);
+            let mut left = vec![0.0; block_len];
+            let mut right = vec![0.0; block_len];
+
+            group.throughput(Throughput::Elements(block_len as u64));
+            let id = BenchmarkId::new(format!("{engine:?}"), block_len);
+            group.bench_function(id, |b| {
+                b.iter(|| {
+                    left.copy_from_slice(&input[..block_len]);
+                    right.copy_from_slice(&input[..block_len]);
+                    efx.process(&mut [&mut left[..], &mut right[..]]);
+                })
+            });
+        }
+    }
+
+    group.finish();
+}
+
+/// The convolution engines on their own, for partition lengths other than the plugin's.
+fn bench_partition_len(c: &mut Criterion) {
+    const BLOCK_LEN: usize = 512;
+    let mut group = c.benchmark_group("partition_len");
+    group.throughput(Throughput::Elements(BLOCK_LEN as u64));
+    let input = noise(BLOCK_LEN);
+    let mut left = vec![0.0; BLOCK_LEN];
+    let mut right = vec![0.0; BLOCK_LEN];
+
+    let sofa = open(FILTER_LEN);
+    let mut filter = Filter::new(FILTER_LEN);
+    sofa.filter(0.5, 0.5, 0.0, &mut filter);
+
+    for partition_len in [32, 64, 128, 256] {
+        let mut render = Renderer::builder(FILTER_LEN)
+            .with_sample_rate(SAMPLE_RATE)
+            .with_partition_len(partition_len)
+            .build()
+            .unwrap();
+        render.set_filter(&filter).unwrap();
+        group.bench_function(BenchmarkId::new("sofar", partition_len), |b| {
+            b.iter(|| render.process_block(&input, &mut left, &mut right).unwrap())
+        });
+
+        let mut ola = OlaConvolver::new(FILTER_LEN, partition_len);
+        ola.set_filter(&filter);
+        group.bench_function(BenchmarkId::new("overlap_add", partition_len), |b| {
+            b.iter(|| ola.process_block(&input, &mut left, &mut right))
+        });
+    }
+
+    group.finish();
+}
+
+/// Several sources at different positions mixed to one binaural output, how a session with one
+/// plugin instance per track scales. The sources move, so filters are looked up on every block.
+fn bench_sources(c: &mut Criterion) {
+    const BLOCK_LEN: usize = 512;
+    let mut group = c.benchmark_group("sources");
+    let input = noise(BLOCK_LEN);
+    let file = dataset(FILTER_LEN);
+
+    for count in [1, 2, 4, 8, 16] {
+        let mut sources: Vec<SpatializerEfx> = (0..count)
+            .map(|_| {
+                let backend = hrtf_backend::open(file.path(), SAMPLE_RATE).unwrap();
+                SpatializerEfx::with_backend(backend, SAMPLE_RATE, BLOCK_LEN)
+            })
+            .collect();
+        let mut left = vec![0.0; BLOCK_LEN];
+        let mut right = vec![0.0; BLOCK_LEN];
+        let mut mix = [vec![0.0; BLOCK_LEN], vec![0.0; BLOCK_LEN]];
+        let mut azimuth = 0.0;
+
+        group.throughput(Throughput::Elements((count * BLOCK_LEN) as u64));
+        group.bench_function(BenchmarkId::from_parameter(count), |b| {
+            b.iter(|| {
+                azimuth += 1.0;
+                mix.iter_mut().for_each(|channel| channel.fill(0.0));
+                for (i, efx) in sources.iter_mut().enumerate() {
+                    efx.set_spherical_position(azimuth + i as f32 * 360.0 / count as f32, 0.0, 1.0);
+                    left.copy_from_slice(&input);
+                    right.copy_from_slice(&input);
+                    efx.process(&mut [&mut left[..], &mut right[..]]);
+                    for (mix, out) in mix.iter_mut().zip([&left, &right]) {
+                        mix.iter_mut().zip(out.iter()).for_each(|(m, s)| *m += s);
+                    }
+                }
+                black_box(&mix);
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_load,
+    bench_filter_lookup,
+    bench_set_filter,
+    bench_block_size,
+    bench_partition_len,
+    bench_sources
+);
+criterion_main!(benches);
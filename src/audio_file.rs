@@ -0,0 +1,36 @@
+// WAV reading shared by the offline renderer and the real-time player
+
+use hound::{SampleFormat, WavReader};
+
+use std::io::Read;
+use std::path::Path;
+
+/// Read a WAV file of any channel count and bit depth, downmixed to a single channel of floats.
+/// Returns the samples and the sample rate.
+pub fn read_mono<P: AsRef<Path>>(path: P) -> Result<(Vec<f32>, u32), hound::Error> {
+    let reader = WavReader::open(path)?;
+    let sample_rate = reader.spec().sample_rate;
+
+    Ok((downmix(reader)?, sample_rate))
+}
+
+fn downmix<R: Read>(mut reader: WavReader<R>) -> Result<Vec<f32>, hound::Error> {
+    let spec = reader.spec();
+    let channels = spec.channels as usize;
+
+    let samples: Vec<f32> = match spec.sample_format {
+        SampleFormat::Float => reader.samples::<f32>().collect::<Result<_, _>>()?,
+        SampleFormat::Int => {
+            let scale = 1.0 / (1i64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .map(|s| s.map(|s| s as f32 * scale))
+                .collect::<Result<_, _>>()?
+        }
+    };
+
+    Ok(samples
+        .chunks_exact(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect())
+}
@@ -0,0 +1,208 @@
+// Position automation for offline renders, a list of keyframes read from CSV or JSON:
+//
+//     time,azimuth,elevation,distance
+//     0.0,0,0,1
+//     2.5,90,0,1
+//
+// Times are in seconds, azimuth and elevation in degrees (SOFA convention, 90 degrees azimuth is
+// left), distance in metres. Positions in between keyframes are interpolated linearly. Elevations
+// and distances outside the ranges of the position parameters are rejected when loading.
+
+use serde::{Deserialize, Serialize};
+
+use crate::spatializer_efx::CoordParam;
+
+use std::path::Path;
+
+/// Distance used when a CSV row leaves it out.
+pub const DEFAULT_DISTANCE: f32 = 1.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Keyframe {
+    pub time: f64,
+    pub azimuth: f32,
+    pub elevation: f32,
+    #[serde(default = "default_distance")]
+    pub distance: f32,
+}
+
+fn default_distance() -> f32 {
+    DEFAULT_DISTANCE
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Automation {
+    /// Sorted by time
+    keyframes: Vec<Keyframe>,
+}
+
+impl Automation {
+    pub fn new(mut keyframes: Vec<Keyframe>) -> Result<Self, String> {
+        if keyframes.is_empty() {
+            return Err(String::from("the automation needs at least one keyframe"));
+        }
+        if let Some(k) = keyframes.iter().find(|k| {
+            !(k.time.is_finite()
+                && k.azimuth.is_finite()
+                && k.elevation.is_finite()
+                && k.distance.is_finite())
+        }) {
+            return Err(format!("keyframe at {} s contains a value that is not a number", k.time));
+        }
+        // azimuths are wrapped, but anything else out of range would stop a render halfway
+        for k in &keyframes {
+            CoordParam::Elevation
+                .validate(k.elevation)
+                .and_then(|_| CoordParam::Distance.validate(k.distance))
+                .map_err(|err| format!("keyframe at {} s: {err}", k.time))?;
+        }
+
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Ok(Automation { keyframes })
+    }
+
+    /// A single position that never changes.
+    pub fn fixed(azimuth: f32, elevation: f32, distance: f32) -> Self {
+        Automation {
+            keyframes: vec![Keyframe {
+                time: 0.0,
+                azimuth,
+                elevation,
+                distance,
+            }],
+        }
+    }
+
+    /// Load a `.json` or `.csv` automation file, depending on the extension.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+
+        if path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("json"))
+        {
+            Self::parse_json(&text)
+        } else {
+            Self::parse_csv(&text)
+        }
+    }
+
+    /// A JSON array of `{"time", "azimuth", "elevation", "distance"}` objects.
+    pub fn parse_json(text: &str) -> Result<Self, String> {
+        let keyframes: Vec<Keyframe> = serde_json::from_str(text).map_err(|err| err.to_string())?;
+        Self::new(keyframes)
+    }
+
+    /// `time,azimuth,elevation[,distance]` rows. A header row and lines starting with `#` are
+    /// skipped.
+    pub fn parse_csv(text: &str) -> Result<Self, String> {
+        let mut keyframes = Vec::new();
+
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with("time") {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            if fields.len() < 3 {
+                return Err(format!("line {}: expected time,azimuth,elevation[,distance]", idx + 1));
+            }
+
+            let number = |field: &str| {
+                field
+                    .parse::<f64>()
+                    .map_err(|_| format!("line {}: '{field}' is not a number", idx + 1))
+            };
+            keyframes.push(Keyframe {
+                time: number(fields[0])?,
+                azimuth: number(fields[1])? as f32,
+                elevation: number(fields[2])? as f32,
+                distance: match fields.get(3) {
+                    Some(field) => number(field)? as f32,
+                    None => DEFAULT_DISTANCE,
+                },
+            });
+        }
+
+        Self::new(keyframes)
+    }
+
+    /// The (azimuth, elevation, distance) at `time` seconds. Azimuth takes the shortest way around.
+    pub fn position_at(&self, time: f64) -> (f32, f32, f32) {
+        let next = self.keyframes.partition_point(|k| k.time <= time);
+        if next == 0 {
+            let k = &self.keyframes[0];
+            return (k.azimuth, k.elevation, k.distance);
+        }
+        if next == self.keyframes.len() {
+            let k = &self.keyframes[next - 1];
+            return (k.azimuth, k.elevation, k.distance);
+        }
+
+        let (a, b) = (&self.keyframes[next - 1], &self.keyframes[next]);
+        let t = ((time - a.time) / (b.time - a.time)) as f32;
+        let azimuth_delta = (b.azimuth - a.azimuth + 540.0).rem_euclid(360.0) - 180.0;
+
+        (
+            a.azimuth + azimuth_delta * t,
+            a.elevation + (b.elevation - a.elevation) * t,
+            a.distance + (b.distance - a.distance) * t,
+        )
+    }
+
+    pub fn keyframes(&self) -> &[Keyframe] {
+        &self.keyframes
+    }
+
+    /// Time of the last keyframe in seconds.
+    pub fn duration(&self) -> f64 {
+        self.keyframes.last().map_or(0.0, |k| k.time)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    #[test]
+    fn test_parse_and_interpolate() {
+        let csv = "time,azimuth,elevation,distance
+# wrap around the back
+0.0,170,0,1
+1.0,-170,10
+";
+        let automation = Automation::parse_csv(csv).unwrap();
+        let (az, el, dist) = automation.position_at(0.5);
+        assert_approx_eq!(az, 180.0);
+        assert_approx_eq!(el, 5.0);
+        assert_approx_eq!(dist, 1.0);
+
+        let json = r#"[{"time": 1.0, "azimuth": -170, "elevation": 10},
+                       {"time": 0.0, "azimuth": 170, "elevation": 0, "distance": 1}]"#;
+        assert_eq!(Automation::parse_json(json).unwrap(), automation);
+
+        // held before the first and after the last keyframe
+        assert_approx_eq!(automation.position_at(-1.0).0, 170.0);
+        assert_approx_eq!(automation.position_at(5.0).0, -170.0);
+    }
+
+    #[test]
+    fn test_rejects_nan() {
+        assert!(Automation::parse_csv("0.0,NaN,0").is_err());
+        assert!(Automation::parse_csv("").is_err());
+    }
+
+    #[test]
+    fn test_rejects_out_of_range() {
+        let err = Automation::parse_csv("0.0,0,0\n2.5,0,120").unwrap_err();
+        assert!(err.starts_with("keyframe at 2.5 s: invalid value 120 for Elevation"), "{err}");
+        let err = Automation::parse_csv("0.0,0,0,1\n1.0,0,0,0").unwrap_err();
+        assert!(err.starts_with("keyframe at 1 s: invalid value 0 for Distance"), "{err}");
+
+        // azimuths in either convention are fine
+        assert!(Automation::parse_csv("0.0,-170,0\n1.0,350,0").is_ok());
+    }
+}
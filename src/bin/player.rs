@@ -0,0 +1,575 @@
+// Real-time binaural player, plays a WAV file through the same DSP core as the plugin with the
+// source position controlled from the terminal
+// usage: target/release/ase-player [OPTIONS] <input wav> <sofa file path>
+//
+// Type `help` while playing for the list of commands. With `--output` the audio goes to a WAV file
+// in real time instead of a sound card, for machines without one.
+
+use anyhow::{anyhow, bail, Context, Error};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{BufferSize, FromSample, SampleRate, SizedSample, StreamConfig};
+use hound::{SampleFormat, WavSpec, WavWriter};
+use ringbuf::{HeapConsumer, HeapProducer, HeapRb};
+
+use ase_project::audio_file;
+use ase_project::hrtf_backend;
+use ase_project::spatializer_efx::{CoordParam, SpatializerEfx};
+
+use std::io::BufRead;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use std::{env, thread};
+
+// Frames rendered at a time by the render thread
+const BLOCK_LEN: usize = 512;
+// Frames the render thread keeps queued ahead of the output, the headroom against underruns
+const QUEUE_LEN: usize = BLOCK_LEN * 8;
+// Output buffer size in frames when the device or the user doesn't pick one
+const DEFAULT_BUFFER_LEN: usize = 512;
+
+const USAGE: &str = "Usage: ase-player [OPTIONS] INPUT_WAV HRTF_FILE
+
+Options:
+  --loop                 Start over at the end of the file
+  --position AZ,EL,DIST  Initial position in degrees and metres (default 0,0,1)
+  --device NAME          Output device, the first one whose name contains NAME
+  --list-devices         Print the output devices and exit
+  --buffer FRAMES        Output buffer size
+  --output FILE          Write to a WAV file in real time instead of a device
+  --seconds SECONDS      Stop after this long";
+
+const HELP: &str = "Commands:
+  az DEG / el DEG / dist M   set azimuth, elevation or distance
+  pos AZ EL DIST             set all three
+  a / d                      turn the source 15 degrees left / right
+  w / s                      move the source 10 degrees up / down
+  + / -                      move the source further away / closer
+  q                          quit";
+
+struct Options {
+    input: String,
+    sofa: String,
+    looping: bool,
+    position: (f32, f32, f32),
+    device: Option<String>,
+    list_devices: bool,
+    help: bool,
+    buffer_len: Option<usize>,
+    output: Option<String>,
+    seconds: Option<f64>,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, Error> {
+    let mut options = Options {
+        input: String::new(),
+        sofa: String::new(),
+        looping: false,
+        position: (0.0, 0.0, 1.0),
+        device: None,
+        list_devices: false,
+        help: false,
+        buffer_len: None,
+        output: None,
+        seconds: None,
+    };
+    let mut positional = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| anyhow!("{arg} needs a value\n\n{USAGE}"))
+        };
+        match arg.as_str() {
+            "--loop" => options.looping = true,
+            "--list-devices" => options.list_devices = true,
+            "--device" => options.device = Some(value()?.clone()),
+            "--output" => options.output = Some(value()?.clone()),
+            "--buffer" => options.buffer_len = Some(value()?.parse().context("Invalid buffer size")?),
+            "--seconds" => options.seconds = Some(value()?.parse().context("Invalid duration")?),
+            "--position" => {
+                let numbers = value()?
+                    .split(',')
+                    .map(|n| n.trim().parse::<f32>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .context("Invalid position")?;
+                let [azimuth, elevation, distance] = numbers[..] else {
+                    bail!("--position needs AZ,EL,DIST");
+                };
+                options.position = (azimuth, elevation, distance);
+            }
+            "-h" | "--help" => options.help = true,
+            _ if arg.starts_with("--") => bail!("Unknown option {arg}\n\n{USAGE}"),
+            _ => positional.push(arg.clone()),
+        }
+    }
+
+    if !options.list_devices && !options.help {
+        let [input, sofa] = &positional[..] else {
+            bail!("{USAGE}");
+        };
+        options.input = input.clone();
+        options.sofa = sofa.clone();
+    }
+
+    Ok(options)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Command {
+    Set(CoordParam, f32),
+    /// Add to the current value
+    Nudge(CoordParam, f32),
+    Position(f32, f32, f32),
+    Help,
+    Quit,
+}
+
+fn parse_command(line: &str) -> Result<Command, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let number = |word: &str| {
+        word.parse::<f32>()
+            .map_err(|_| format!("'{word}' is not a number"))
+    };
+
+    let command = match words[..] {
+        ["a"] => Command::Nudge(CoordParam::Azimuth, 15.0),
+        ["d"] => Command::Nudge(CoordParam::Azimuth, -15.0),
+        ["w"] => Command::Nudge(CoordParam::Elevation, 10.0),
+        ["s"] => Command::Nudge(CoordParam::Elevation, -10.0),
+        ["+"] => Command::Nudge(CoordParam::Distance, 0.25),
+        ["-"] => Command::Nudge(CoordParam::Distance, -0.25),
+        ["az", value] => Command::Set(CoordParam::Azimuth, number(value)?),
+        ["el", value] => Command::Set(CoordParam::Elevation, number(value)?),
+        ["dist", value] => Command::Set(CoordParam::Distance, number(value)?),
+        ["pos", az, el, dist] => Command::Position(number(az)?, number(el)?, number(dist)?),
+        ["h"] | ["help"] => Command::Help,
+        ["q"] | ["quit"] => Command::Quit,
+        _ => return Err(format!("unknown command '{}', type 'help' for a list", line.trim())),
+    };
+
+    Ok(command)
+}
+
+fn apply_command(efx: &mut SpatializerEfx, command: Command) -> Result<(), Error> {
+    match command {
+        Command::Set(param, value) => efx.set_param(param, value)?,
+        Command::Nudge(param, delta) => {
+            let mut value = efx.get_param(param) + delta;
+            if param == CoordParam::Azimuth {
+                value = value.rem_euclid(360.0);
+            }
+            efx.set_param(param, value)?;
+        }
+        Command::Position(azimuth, elevation, distance) => {
+            efx.set_param(CoordParam::Azimuth, azimuth)?;
+            efx.set_param(CoordParam::Elevation, elevation)?;
+            efx.set_param(CoordParam::Distance, distance)?;
+        }
+        Command::Help | Command::Quit => return Ok(()),
+    }
+
+    println!(
+        "Pos: az {:.0} deg, el {:.0} deg, dist {:.2} m",
+        efx.get_param(CoordParam::Azimuth),
+        efx.get_param(CoordParam::Elevation),
+        efx.get_param(CoordParam::Distance)
+    );
+
+    Ok(())
+}
+
+/// The mono input, optionally looping forever
+struct Source {
+    samples: Vec<f32>,
+    pos: usize,
+    looping: bool,
+}
+
+impl Source {
+    /// Fill `out`, with silence past the end. Returns false once the end has been reached.
+    fn read(&mut self, out: &mut [f32]) -> bool {
+        for sample in out.iter_mut() {
+            if self.pos == self.samples.len() && self.looping {
+                self.pos = 0;
+            }
+            *sample = self.samples.get(self.pos).copied().unwrap_or(0.0);
+            self.pos = (self.pos + 1).min(self.samples.len());
+        }
+
+        self.looping || self.pos < self.samples.len()
+    }
+}
+
+/// Flags shared between the threads
+#[derive(Default)]
+struct State {
+    quit: AtomicBool,
+    /// The render thread queued the last block, including the tail of the filter
+    rendered: AtomicBool,
+    /// The output played everything the render thread queued
+    drained: AtomicBool,
+    /// Output buffers that were not completely filled
+    underruns: AtomicUsize,
+}
+
+/// Renders blocks ahead of the output until the ring buffer is full, applying position commands in
+/// between. Runs on its own thread so the audio callback only copies samples.
+fn render(
+    mut efx: SpatializerEfx,
+    mut source: Source,
+    mut producer: HeapProducer<f32>,
+    commands: Receiver<Command>,
+    state: Arc<State>,
+) {
+    let mut left = vec![0.0; BLOCK_LEN];
+    let mut right = vec![0.0; BLOCK_LEN];
+    let mut interleaved = vec![0.0; BLOCK_LEN * 2];
+    // ring out the convolution tail after the end of the file
+    let mut tail = efx.filter_len();
+
+    while !state.quit.load(Ordering::Relaxed) {
+        for command in commands.try_iter() {
+            if let Err(err) = apply_command(&mut efx, command) {
+                eprintln!("{err}");
+            }
+        }
+
+        if producer.free_len() < interleaved.len() {
+            thread::sleep(Duration::from_millis(1));
+            continue;
+        }
+
+        if !source.read(&mut left) {
+            if tail == 0 {
+                break;
+            }
+            tail = tail.saturating_sub(BLOCK_LEN);
+        }
+        right.copy_from_slice(&left);
+        efx.process(&mut [&mut left, &mut right]);
+
+        for (frame, (l, r)) in interleaved.chunks_exact_mut(2).zip(left.iter().zip(&right)) {
+            frame[0] = *l;
+            frame[1] = *r;
+        }
+        producer.push_slice(&interleaved);
+    }
+
+    state.rendered.store(true, Ordering::Relaxed);
+}
+
+/// Copy whole frames from the ring buffer to an interleaved output buffer with `channels` channels,
+/// the left and right ear go to the first two. Missing frames are filled with silence instead of
+/// blocking, this runs in the audio callback.
+fn fill_output<T>(data: &mut [T], channels: usize, consumer: &mut HeapConsumer<f32>, state: &State)
+where
+    T: SizedSample + FromSample<f32>,
+{
+    let mut underrun = false;
+    for frame in data.chunks_exact_mut(channels) {
+        let (left, right) = if consumer.len() >= 2 {
+            (consumer.pop().unwrap_or(0.0), consumer.pop().unwrap_or(0.0))
+        } else {
+            underrun = true;
+            (0.0, 0.0)
+        };
+
+        for (channel, sample) in frame.iter_mut().enumerate() {
+            *sample = T::from_sample(match channel {
+                0 => left,
+                1 => right,
+                _ => 0.0,
+            });
+        }
+    }
+
+    if underrun {
+        if state.rendered.load(Ordering::Relaxed) {
+            state.drained.store(true, Ordering::Relaxed);
+        } else {
+            state.underruns.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+fn main() -> Result<(), Error> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = parse_args(&args)?;
+    if options.help {
+        println!("{USAGE}");
+        return Ok(());
+    }
+
+    let host = cpal::default_host();
+    if options.list_devices {
+        for device in host.output_devices()? {
+            println!("{}", device.name()?);
+        }
+        return Ok(());
+    }
+
+    let (input, input_rate) =
+        audio_file::read_mono(&options.input).context("Open wav file failed")?;
+    println!("Input: {} frames at {} Hz", input.len(), input_rate);
+
+    let backend = hrtf_backend::open(&options.sofa, input_rate as f32)
+        .context("Open HRTF file failed")?;
+    let mut efx = SpatializerEfx::with_backend(backend, input_rate as f32, BLOCK_LEN);
+    apply_command(
+        &mut efx,
+        Command::Position(options.position.0, options.position.1, options.position.2),
+    )?;
+
+    let source = Source {
+        samples: input,
+        pos: 0,
+        looping: options.looping,
+    };
+
+    let (mut producer, consumer) = HeapRb::<f32>::new(QUEUE_LEN * 2).split();
+    // start with a block of silence so the first callbacks don't underrun
+    producer.push_slice(&[0.0; BLOCK_LEN * 2]);
+
+    let state = Arc::new(State::default());
+    let (command_sender, commands) = mpsc::channel();
+
+    let render_state = state.clone();
+    let render_thread =
+        thread::spawn(move || render(efx, source, producer, commands, render_state));
+
+    // commands are read line by line so this works both from a terminal and from a pipe, the end of
+    // the input just means there are no more commands
+    let stdin_state = state.clone();
+    thread::spawn(move || {
+        println!("{HELP}");
+        for line in std::io::stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            if line.trim().is_empty() {
+                continue;
+            }
+            match parse_command(&line) {
+                Ok(Command::Quit) => {
+                    stdin_state.quit.store(true, Ordering::Relaxed);
+                    break;
+                }
+                Ok(Command::Help) => println!("{HELP}"),
+                Ok(command) => {
+                    if command_sender.send(command).is_err() {
+                        break;
+                    }
+                }
+                Err(err) => eprintln!("{err}"),
+            }
+        }
+    });
+
+    let deadline = options
+        .seconds
+        .map(|seconds| Instant::now() + Duration::from_secs_f64(seconds));
+    let running = || {
+        !state.quit.load(Ordering::Relaxed)
+            && !state.drained.load(Ordering::Relaxed)
+            && deadline.is_none_or(|deadline| Instant::now() < deadline)
+    };
+
+    match &options.output {
+        Some(path) => play_to_file(path, input_rate, &options, consumer, &state, running)?,
+        None => play_to_device(&host, input_rate, &options, consumer, &state, running)?,
+    }
+
+    state.quit.store(true, Ordering::Relaxed);
+    render_thread
+        .join()
+        .map_err(|_| anyhow!("The render thread panicked"))?;
+    println!("Underruns: {}", state.underruns.load(Ordering::Relaxed));
+
+    Ok(())
+}
+
+fn play_to_device(
+    host: &cpal::Host,
+    sample_rate: u32,
+    options: &Options,
+    mut consumer: HeapConsumer<f32>,
+    state: &Arc<State>,
+    running: impl Fn() -> bool,
+) -> Result<(), Error> {
+    let device = match &options.device {
+        Some(name) => host
+            .output_devices()?
+            .find(|device| device.name().is_ok_and(|n| n.contains(name.as_str())))
+            .ok_or_else(|| anyhow!("No output device matching '{name}', try --list-devices"))?,
+        None => host
+            .default_output_device()
+            .ok_or_else(|| anyhow!("No output device available"))?,
+    };
+    println!("Output device: {}", device.name()?);
+
+    // the device has to run at the file's rate, there is no resampler
+    let supported = device
+        .supported_output_configs()?
+        .filter(|config| {
+            config.channels() >= 2
+                && config.min_sample_rate().0 <= sample_rate
+                && sample_rate <= config.max_sample_rate().0
+        })
+        .max_by_key(|config| config.sample_format() == cpal::SampleFormat::F32)
+        .ok_or_else(|| anyhow!("The output device doesn't support {sample_rate} Hz stereo"))?
+        .with_sample_rate(SampleRate(sample_rate));
+
+    let mut config = StreamConfig::from(supported.clone());
+    if let Some(buffer_len) = options.buffer_len {
+        config.buffer_size = BufferSize::Fixed(buffer_len as u32);
+    }
+    println!("Output config: {:?}", config);
+
+    let channels = config.channels as usize;
+    let callback_state = state.clone();
+    let err_fn = |err| eprintln!("An error occurred on stream: {}", err);
+    let stream = match supported.sample_format() {
+        cpal::SampleFormat::F32 => device.build_output_stream(
+            &config,
+            move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                fill_output(data, channels, &mut consumer, &callback_state)
+            },
+            err_fn,
+            None,
+        )?,
+        cpal::SampleFormat::I16 => device.build_output_stream(
+            &config,
+            move |data: &mut [i16], _: &cpal::OutputCallbackInfo| {
+                fill_output(data, channels, &mut consumer, &callback_state)
+            },
+            err_fn,
+            None,
+        )?,
+        cpal::SampleFormat::U16 => device.build_output_stream(
+            &config,
+            move |data: &mut [u16], _: &cpal::OutputCallbackInfo| {
+                fill_output(data, channels, &mut consumer, &callback_state)
+            },
+            err_fn,
+            None,
+        )?,
+        fmt => bail!("Unsupported sample format {:?}", fmt),
+    };
+
+    stream.play()?;
+    while running() {
+        thread::sleep(Duration::from_millis(20));
+    }
+
+    Ok(())
+}
+
+/// Stand-in for a sound card: pulls one buffer at a time at the pace a device would and writes it
+/// to a stereo float WAV file.
+fn play_to_file(
+    path: &str,
+    sample_rate: u32,
+    options: &Options,
+    mut consumer: HeapConsumer<f32>,
+    state: &State,
+    running: impl Fn() -> bool,
+) -> Result<(), Error> {
+    let spec = WavSpec {
+        channels: 2,
+        sample_rate,
+        bits_per_sample: 32,
+        sample_format: SampleFormat::Float,
+    };
+    let mut writer = WavWriter::create(path, spec).context("Create output wav failed")?;
+
+    let buffer_len = options.buffer_len.unwrap_or(DEFAULT_BUFFER_LEN);
+    let period = Duration::from_secs_f64(buffer_len as f64 / sample_rate as f64);
+    let mut data = vec![0.0f32; buffer_len * 2];
+    let mut next = Instant::now();
+
+    while running() {
+        fill_output(&mut data, 2, &mut consumer, state);
+        for sample in &data {
+            writer.write_sample(*sample)?;
+        }
+
+        next += period;
+        thread::sleep(next.saturating_duration_since(Instant::now()));
+    }
+
+    writer.finalize()?;
+    println!("Wrote {}", path);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(parse_command("az -45"), Ok(Command::Set(CoordParam::Azimuth, -45.0)));
+        assert_eq!(parse_command(" a "), Ok(Command::Nudge(CoordParam::Azimuth, 15.0)));
+        assert_eq!(parse_command("pos 90 10 2"), Ok(Command::Position(90.0, 10.0, 2.0)));
+        assert!(parse_command("el up").is_err());
+        assert!(parse_command("jump").is_err());
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args = |args: &[&str]| {
+            let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+            parse_args(&args)
+        };
+        let options = args(&["--loop", "--position", "90,0,2", "in.wav", "hrtf.sofa"]).unwrap();
+        assert_eq!((options.input.as_str(), options.sofa.as_str()), ("in.wav", "hrtf.sofa"));
+        assert_eq!(options.position, (90.0, 0.0, 2.0));
+        assert!(options.looping);
+
+        // asking for help is not an error, whatever else is missing
+        assert!(args(&["--help"]).unwrap().help);
+        assert!(args(&["-h", "in.wav"]).unwrap().help);
+        assert!(args(&["in.wav"]).is_err());
+        assert!(args(&["--bogus", "in.wav", "hrtf.sofa"]).is_err());
+    }
+
+    #[test]
+    fn test_underrun_fills_silence() {
+        let (mut producer, mut consumer) = HeapRb::<f32>::new(16).split();
+        producer.push_slice(&[0.5, -0.5, 0.25, -0.25]);
+        let state = State::default();
+
+        // four channels, two frames available out of three
+        let mut data = [1.0f32; 12];
+        fill_output(&mut data, 4, &mut consumer, &state);
+        assert_eq!(
+            data,
+            [0.5, -0.5, 0.0, 0.0, 0.25, -0.25, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]
+        );
+        assert_eq!(state.underruns.load(Ordering::Relaxed), 1);
+
+        // after the last block it's the end of playback instead
+        state.rendered.store(true, Ordering::Relaxed);
+        fill_output(&mut data, 4, &mut consumer, &state);
+        assert!(state.drained.load(Ordering::Relaxed));
+        assert_eq!(state.underruns.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_looping_source() {
+        let mut source = Source {
+            samples: vec![1.0, 2.0, 3.0],
+            pos: 0,
+            looping: true,
+        };
+        let mut out = [0.0; 7];
+        assert!(source.read(&mut out));
+        assert_eq!(out, [1.0, 2.0, 3.0, 1.0, 2.0, 3.0, 1.0]);
+
+        source.looping = false;
+        assert!(!source.read(&mut out));
+        assert_eq!(out, [2.0, 3.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+    }
+}
@@ -0,0 +1,109 @@
+// Offline binaural render of a WAV file with position automation, using the same DSP core as the
+// plugin
+// usage: target/release/ase-render [--hp-eq <eq file>] <input wav> <sofa or .bin file path>
+//            <automation csv/json> <output wav>
+//        target/release/ase-render --params
+
+use anyhow::{anyhow, bail, Context, Error};
+use hound::{SampleFormat, WavSpec, WavWriter};
+
+use ase_project::audio_file;
+use ase_project::automation::Automation;
+use ase_project::headphone_eq::{EqProfile, HeadphoneEq};
+use ase_project::hrtf_backend;
+use ase_project::spatializer_efx::SpatializerEfx;
+
+use std::env;
+
+// Positions are updated from the automation once per block
+const BLOCK_LEN: usize = 256;
+
+fn main() -> Result<(), Error> {
+    let mut args: Vec<String> = env::args().collect();
+
+    if args.get(1).is_some_and(|arg| arg == "--params") {
+        for info in SpatializerEfx::params() {
+            println!(
+                "{:<10} {:>8} to {:>6} {:<3} (default {})",
+                info.name,
+                info.min,
+                info.max,
+                info.unit.symbol(),
+                info.default
+            );
+        }
+        return Ok(());
+    }
+
+    // headphone EQ profile, an FIR .wav or an AutoEQ ParametricEQ.txt
+    let mut hp_eq_path = None;
+    if args.get(1).is_some_and(|arg| arg == "--hp-eq") && args.len() > 2 {
+        hp_eq_path = Some(args.remove(2));
+        args.remove(1);
+    }
+
+    if args.len() != 5 {
+        bail!(
+            "Usage: {0} [--hp-eq EQ_FILE] INPUT_WAV HRTF_FILE AUTOMATION_FILE OUTPUT_WAV\n       \
+             {0} --params",
+            args[0].clone()
+        );
+    }
+
+    let (input, input_rate) = audio_file::read_mono(&args[1]).context("Open wav file failed")?;
+    println!("Input: {} frames at {} Hz", input.len(), input_rate);
+
+    let sample_rate = input_rate as f32;
+    let backend = hrtf_backend::open_offline(&args[2], sample_rate).context("Open HRTF file failed")?;
+    let filt_len = backend.filter_len();
+    let automation = Automation::load(&args[3])
+        .map_err(|err| anyhow!(err))
+        .context("Open automation file failed")?;
+
+    let mut efx = SpatializerEfx::with_backend(backend, sample_rate, BLOCK_LEN);
+    if let Some(path) = hp_eq_path {
+        let hp_eq = EqProfile::load(&path)
+            .and_then(|profile| HeadphoneEq::new(&profile, sample_rate))
+            .context("Open headphone EQ failed")?;
+        efx.set_headphone_eq(Some(hp_eq));
+    }
+
+    let output_spec = WavSpec {
+        channels: 2,
+        sample_rate: input_rate,
+        bits_per_sample: 32,
+        sample_format: SampleFormat::Float,
+    };
+    let mut writer = WavWriter::create(&args[4], output_spec).context("Create output wav failed")?;
+
+    // Keep going for one filter length past the end so the convolution tail rings out
+    let total_len = input.len() + filt_len;
+    let mut left = vec![0.0; BLOCK_LEN];
+    let mut right = vec![0.0; BLOCK_LEN];
+
+    for start in (0..total_len).step_by(BLOCK_LEN) {
+        let len = BLOCK_LEN.min(total_len - start);
+
+        // the keyframes were range checked on load and interpolating between them stays in range,
+        // apart from azimuths across 0 degrees, which are wrapped
+        let (azimuth, elevation, distance) = automation.position_at(start as f64 / sample_rate as f64);
+        efx.set_spherical_position(azimuth, elevation, distance);
+
+        for i in 0..len {
+            let sample = input.get(start + i).copied().unwrap_or(0.0);
+            left[i] = sample;
+            right[i] = sample;
+        }
+        efx.process(&mut [&mut left[..len], &mut right[..len]]);
+
+        for (l, r) in Iterator::zip(left[..len].iter(), right[..len].iter()) {
+            writer.write_sample(*l)?;
+            writer.write_sample(*r)?;
+        }
+    }
+
+    writer.finalize()?;
+    println!("Wrote {} frames to {}", total_len, args[4]);
+
+    Ok(())
+}
@@ -0,0 +1,57 @@
+// Prints what is inside a SOFA dataset before it gets loaded into the plugin
+// usage: target/release/ase-sofa-inspect <sofa file path>...
+
+use anyhow::{bail, Context, Error};
+use ase_project::sofa_info::{CoordinateType, SofaInfo};
+
+use std::env;
+
+// Size of the azimuth/elevation coverage map, 5 degrees per column and 10 degrees per row
+const MAP_COLS: usize = 72;
+const MAP_ROWS: usize = 18;
+
+fn main() -> Result<(), Error> {
+    let args: Vec<String> = env::args().collect();
+
+    if args.len() < 2 {
+        bail!("Usage: {} SOFA_FILE...", args[0].clone());
+    }
+
+    for path in &args[1..] {
+        let info = SofaInfo::open(path).with_context(|| format!("Open sofa file {path} failed"))?;
+        print_info(path, &info);
+    }
+
+    Ok(())
+}
+
+fn print_info(path: &str, info: &SofaInfo) {
+    let (min_dist, max_dist) = info.distance_range();
+    let coordinates = match &info.coordinate_type {
+        CoordinateType::Spherical => "spherical",
+        CoordinateType::Cartesian => "cartesian",
+        CoordinateType::Other(name) => name.as_str(),
+    };
+
+    println!("{path}");
+    println!(
+        "  Convention:    {} {} ({}, {})",
+        info.sofa_conventions, info.sofa_conventions_version, info.conventions, info.data_type
+    );
+    println!("  Sample rate:   {} Hz", info.sample_rate);
+    println!("  Filter length: {} samples", info.filter_len);
+    println!("  Measurements:  {}", info.measurements);
+    println!("  Receivers:     {}", info.receivers);
+    println!("  Coordinates:   {coordinates}");
+    println!("  Distance:      {min_dist:.2} m to {max_dist:.2} m");
+    match info.validate() {
+        Ok(()) => println!("  Validation:    OK"),
+        Err(err) => println!("  Validation:    {err}"),
+    }
+    println!();
+    println!("  Coverage (azimuth across, elevation down):");
+    for line in info.coverage_map(MAP_COLS, MAP_ROWS).lines() {
+        println!("  {line}");
+    }
+    println!();
+}
@@ -0,0 +1,77 @@
+// The plugin as a standalone application for spatializing live input without a DAW, using
+// nih_plug's standalone wrapper with the JACK or ALSA backend
+// usage: target/release/ase-spatializer [--sofa PATH] [--hp-eq PATH] [--position AZ,EL,DIST]
+//        [--inputs PORTS] [--outputs PORTS] [nih_plug standalone options]
+//
+// Everything this doesn't know about is passed on to nih_plug, `--help` lists those options
+// (backend, devices, sample rate, period size...).
+
+use nih_plug::prelude::*;
+
+use ase_project::{Spatializer, StandaloneConfig};
+
+use std::env;
+use std::process::ExitCode;
+
+const USAGE: &str = "Spatializer options:
+  --sofa PATH            HRTF dataset to load
+  --hp-eq PATH           Headphone EQ, an FIR .wav or an AutoEQ ParametricEQ.txt
+  --position AZ,EL,DIST  Initial source position in degrees and metres
+  --inputs PORTS         JACK ports to connect the inputs to, comma separated
+  --outputs PORTS        JACK ports to connect the outputs to, comma separated";
+
+fn main() -> ExitCode {
+    let mut args = env::args();
+    let mut config = StandaloneConfig::default();
+    // the program name and the options for nih_plug's wrapper
+    let mut wrapper_args: Vec<String> = args.next().into_iter().collect();
+
+    while let Some(arg) = args.next() {
+        let option = arg.as_str();
+        if !matches!(option, "--sofa" | "--hp-eq" | "--position" | "--inputs" | "--outputs") {
+            if option == "--help" || option == "-h" {
+                println!("{USAGE}\n");
+            }
+            wrapper_args.push(arg);
+            continue;
+        }
+
+        let Some(value) = args.next() else {
+            eprintln!("{option} needs a value\n\n{USAGE}");
+            return ExitCode::FAILURE;
+        };
+        match option {
+            "--sofa" => config.sofa_path = Some(value),
+            "--hp-eq" => config.hp_eq_path = Some(value),
+            "--position" => match parse_position(&value) {
+                Some(position) => config.position = Some(position),
+                None => {
+                    eprintln!("Invalid position '{value}', expected AZ,EL,DIST\n\n{USAGE}");
+                    return ExitCode::FAILURE;
+                }
+            },
+            "--inputs" => wrapper_args.extend([String::from("--connect-jack-inputs"), value]),
+            _ => wrapper_args.extend([String::from("--connect-jack-outputs"), value]),
+        }
+    }
+
+    ase_project::set_standalone_config(config);
+
+    if nih_export_standalone_with_args::<Spatializer, _>(wrapper_args) {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+fn parse_position(value: &str) -> Option<(f32, f32, f32)> {
+    let numbers = value
+        .split(',')
+        .map(|n| n.trim().parse::<f32>().ok().filter(|n| n.is_finite()))
+        .collect::<Option<Vec<_>>>()?;
+
+    match numbers[..] {
+        [azimuth, elevation, distance] => Some((azimuth, elevation, distance)),
+        _ => None,
+    }
+}
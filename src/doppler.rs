@@ -0,0 +1,173 @@
+// Doppler shift for moving sources
+// The mono source signal runs through a delay line whose length is the propagation time from the
+// source to the listener. When the distance changes the delay is swept, which shifts the pitch by
+// the radial velocity over the speed of sound, just like the real thing.
+
+use crate::head_model::SPEED_OF_SOUND;
+use crate::spatializer_efx::{CONTROL_LEN, MAX_DISTANCE};
+
+/// The range of the speed of sound the user can dial in, in m/s. Slower speeds exaggerate the
+/// effect.
+pub const MIN_SPEED_OF_SOUND: f32 = 50.0;
+pub const MAX_SPEED_OF_SOUND: f32 = 1000.0;
+
+/// The fastest the delay may change, in samples per sample. This caps the radial velocity at half
+/// the speed of sound so the pitch can never fold over.
+const MAX_SLEW: f64 = 0.5;
+
+pub struct Doppler {
+    sample_rate: f32,
+    speed_of_sound: f32,
+    /// 0 leaves the signal alone, 1 delays it by the real propagation time
+    amount: f32,
+    distance: f32,
+
+    /// Power of two long so the read and write positions can wrap with a mask
+    buffer: Vec<f32>,
+    write_pos: usize,
+    /// Current and target delay in samples, the delay moves towards the target by `step` per sample
+    delay: f64,
+    target: f64,
+    step: f64,
+}
+
+impl Doppler {
+    pub fn new(sample_rate: f32) -> Self {
+        let max_delay = (MAX_DISTANCE / MIN_SPEED_OF_SOUND * sample_rate).ceil() as usize + 2;
+
+        Doppler {
+            sample_rate,
+            speed_of_sound: SPEED_OF_SOUND,
+            amount: 0.0,
+            distance: 1.0,
+            buffer: vec![0.0; max_delay.next_power_of_two()],
+            write_pos: 0,
+            delay: 0.0,
+            target: 0.0,
+            step: 0.0,
+        }
+    }
+
+    /// Scale the propagation delay between 0 (no Doppler) and 1 (physically correct).
+    pub fn set_amount(&mut self, amount: f32) {
+        if amount.is_finite() && amount != self.amount {
+            self.amount = amount.clamp(0.0, 1.0);
+            self.update_target();
+        }
+    }
+
+    /// Set the speed of sound in m/s.
+    pub fn set_speed_of_sound(&mut self, speed: f32) {
+        if speed.is_finite() && speed != self.speed_of_sound {
+            self.speed_of_sound = speed.clamp(MIN_SPEED_OF_SOUND, MAX_SPEED_OF_SOUND);
+            self.update_target();
+        }
+    }
+
+    /// Set the source distance in metres. The delay glides to the new distance over the next
+    /// `CONTROL_LEN` samples, so updating this once per control block gives a smooth sweep at the
+    /// source's radial velocity.
+    pub fn set_distance(&mut self, distance: f32) {
+        if distance.is_finite() && distance != self.distance {
+            self.distance = distance.clamp(0.0, MAX_DISTANCE);
+            self.update_target();
+        }
+    }
+
+    /// The current delay in samples.
+    pub fn delay(&self) -> f64 {
+        self.delay
+    }
+
+    pub fn process(&mut self, samples: &mut [f32]) {
+        let mask = self.buffer.len() - 1;
+
+        for sample in samples.iter_mut() {
+            self.buffer[self.write_pos] = *sample;
+
+            if self.delay != self.target {
+                self.delay += self.step;
+                if (self.target - self.delay) * self.step <= 0.0 {
+                    self.delay = self.target;
+                }
+            }
+
+            // linear interpolation between the two samples around the read position
+            let read_pos = self.write_pos as f64 + self.buffer.len() as f64 - self.delay;
+            let idx = read_pos.floor();
+            let frac = (read_pos - idx) as f32;
+            let idx = idx as usize;
+            let a = self.buffer[idx & mask];
+            let b = self.buffer[(idx + 1) & mask];
+            *sample = a + (b - a) * frac;
+
+            self.write_pos = (self.write_pos + 1) & mask;
+        }
+    }
+
+    /// Clear the delay line and jump straight to the target delay.
+    pub fn reset(&mut self) {
+        self.buffer.fill(0.0);
+        self.write_pos = 0;
+        self.delay = self.target;
+        self.step = 0.0;
+    }
+
+    fn update_target(&mut self) {
+        self.target = (self.amount * self.distance / self.speed_of_sound * self.sample_rate) as f64;
+        self.step = ((self.target - self.delay) / CONTROL_LEN as f64).clamp(-MAX_SLEW, MAX_SLEW);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    /// Frequency of a sine from the average spacing of its upward zero crossings
+    fn frequency(samples: &[f32], sample_rate: f32) -> f32 {
+        let crossings: Vec<usize> = (1..samples.len())
+            .filter(|&i| samples[i - 1] < 0.0 && samples[i] >= 0.0)
+            .collect();
+        let periods = (crossings.len() - 1) as f32;
+
+        sample_rate * periods / (crossings[crossings.len() - 1] - crossings[0]) as f32
+    }
+
+    #[test]
+    fn test_bypassed_without_amount() {
+        let mut doppler = Doppler::new(48000.0);
+        doppler.set_distance(10.0);
+
+        let input: Vec<f32> = (0..256).map(|i| (i as f32 * 0.1).sin()).collect();
+        let mut output = input.clone();
+        doppler.process(&mut output);
+        assert_eq!(input, output);
+    }
+
+    #[test]
+    fn test_approaching_source_raises_pitch() {
+        let sample_rate = 48000.0;
+        let mut doppler = Doppler::new(sample_rate);
+        doppler.set_amount(1.0);
+        doppler.set_distance(MAX_DISTANCE);
+        doppler.reset();
+
+        // approach at 34.3 m/s, a tenth of the speed of sound, for half a second
+        let velocity = SPEED_OF_SOUND / 10.0;
+        let len = sample_rate as usize / 2;
+        let mut output: Vec<f32> = (0..len)
+            .map(|i| (std::f32::consts::TAU * 1000.0 * i as f32 / sample_rate).sin())
+            .collect();
+        for (block, samples) in output.chunks_mut(CONTROL_LEN).enumerate() {
+            let time = (block * CONTROL_LEN) as f32 / sample_rate;
+            doppler.set_distance(MAX_DISTANCE - velocity * time);
+            doppler.process(samples);
+        }
+
+        // skip the start of the sweep, the delay lags one control block behind. A swept delay line
+        // shifts like a moving listener would hear it, by 1 + v / c
+        let expected = 1000.0 * (1.0 + 0.1);
+        assert_approx_eq!(frequency(&output[len / 4..], sample_rate), expected, 2.0);
+    }
+}
@@ -0,0 +1,463 @@
+// The plugin GUI: a top-down pad for azimuth and distance, a side view for elevation and sliders,
+// all bound to the `SpatializerParams` so host automation and the GUI stay in sync. A second page
+// shows the HRTFs in use, see `hrtf_view`. The preset bar above both pages loads, saves, imports
+// and exports `presets`. Below the sliders the headphone EQ profile is loaded, cleared and bypassed.
+
+use nih_plug::prelude::*;
+use nih_plug_egui::egui::{self, Align2, Color32, FontId, Pos2, Rect, Sense, Stroke, Vec2};
+use nih_plug_egui::{create_egui_editor, widgets, EguiState};
+use parking_lot::Mutex;
+
+use crate::hrtf_view::{self, HrtfView, Spectra};
+use crate::meters::Meters;
+use crate::presets::{self, Preset};
+use crate::spatializer_efx::{cartesian_to_spherical, Error, MAX_DISTANCE, MIN_DISTANCE};
+use crate::SpatializerParams;
+
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+const WIDTH: u32 = 660;
+const HEIGHT: u32 = 660;
+/// Side length of the two position pads in points
+const PAD_SIZE: f32 = 300.0;
+/// The pads reach this far from the head in metres, further sources are drawn at the edge
+const PAD_RANGE: f32 = 2.0;
+
+const BACKGROUND: Color32 = Color32::from_rgb(24, 26, 31);
+const GRID: Color32 = Color32::from_rgb(60, 64, 72);
+const HEAD: Color32 = Color32::from_rgb(170, 170, 180);
+const SOURCE: Color32 = Color32::from_rgb(240, 150, 40);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Page {
+    Position,
+    Hrtf,
+}
+
+struct EditorState {
+    page: Page,
+    spectra: Spectra,
+    presets: PresetBrowser,
+    /// Path of the headphone EQ profile, loaded with the Load button
+    hp_eq_path: String,
+}
+
+/// The factory and user presets and what is typed into the preset bar
+struct PresetBrowser {
+    factory: Vec<Preset>,
+    user: Vec<Preset>,
+    /// Name of the last loaded or saved preset, also the name the next save uses
+    name: String,
+    /// Path for importing and exporting
+    file: String,
+    /// The outcome of the last action, or why the loaded preset's files don't match
+    message: Option<String>,
+}
+
+impl PresetBrowser {
+    fn new() -> Self {
+        let mut browser = PresetBrowser {
+            factory: presets::factory(),
+            user: Vec::new(),
+            name: String::new(),
+            file: String::new(),
+            message: None,
+        };
+        browser.refresh();
+        browser
+    }
+
+    /// Reread the user preset directory.
+    fn refresh(&mut self) {
+        let Some(dir) = presets::user_dir() else {
+            return;
+        };
+        let (user, errors) = presets::load_user(&dir);
+        self.user = user;
+        self.message = errors.first().map(|err| err.to_string());
+    }
+
+    fn load(&mut self, preset: &Preset, setter: &ParamSetter) {
+        let state = preset.apply(setter.raw_context.get_state());
+        setter.raw_context.set_state(state);
+
+        self.name = preset.name.clone();
+        let problems: Vec<String> = preset.check_files().iter().map(|p| p.to_string()).collect();
+        self.message = (!problems.is_empty()).then(|| problems.join(", "));
+    }
+
+    fn save(&mut self, setter: &ParamSetter) {
+        let preset = Preset::from_state(&self.name, &setter.raw_context.get_state());
+        let result = presets::user_dir()
+            .ok_or_else(|| String::from("no user preset directory"))
+            .and_then(|dir| presets::save_user(&dir, &preset).map_err(|err| err.to_string()));
+
+        self.refresh();
+        self.message = Some(match result {
+            Ok(path) => format!("Saved {}", path.display()),
+            Err(err) => err,
+        });
+    }
+
+    fn import(&mut self, setter: &ParamSetter) {
+        let result = match presets::user_dir() {
+            Some(dir) => presets::import_user(&dir, &self.file),
+            None => Preset::import(&self.file),
+        };
+
+        self.refresh();
+        match result {
+            Ok(preset) => self.load(&preset, setter),
+            Err(err) => self.message = Some(err.to_string()),
+        }
+    }
+
+    fn export(&mut self, setter: &ParamSetter) {
+        let preset = Preset::from_state(&self.name, &setter.raw_context.get_state());
+        self.message = Some(match preset.export(&self.file) {
+            Ok(()) => format!("Exported to {}", self.file),
+            Err(err) => err.to_string(),
+        });
+    }
+}
+
+pub(crate) fn default_state() -> Arc<EguiState> {
+    EguiState::from_size(WIDTH, HEIGHT)
+}
+
+pub(crate) fn create(
+    params: Arc<SpatializerParams>,
+    editor_state: Arc<EguiState>,
+    hrtf: Arc<Mutex<HrtfView>>,
+    meters: Arc<Meters>,
+    hp_eq_error: Arc<Mutex<Option<Error>>>,
+) -> Option<Box<dyn Editor>> {
+    let hp_eq_path = params.hp_eq_path.read().unwrap().clone().unwrap_or_default();
+    create_egui_editor(
+        editor_state,
+        EditorState {
+            page: Page::Position,
+            spectra: Spectra::default(),
+            presets: PresetBrowser::new(),
+            hp_eq_path,
+        },
+        |_, _| {},
+        move |egui_ctx, setter, state| {
+            egui::CentralPanel::default().show(egui_ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.selectable_value(&mut state.page, Page::Position, "Position");
+                    ui.selectable_value(&mut state.page, Page::Hrtf, "HRTF");
+                });
+                preset_bar(ui, &mut state.presets, setter);
+                ui.separator();
+
+                if state.page == Page::Hrtf {
+                    // drawn from a copy, the audio thread skips its updates while this is locked
+                    let view = hrtf.lock().clone();
+                    hrtf_view::show(ui, &view, &mut state.spectra);
+                    return;
+                }
+
+                let (x, y, z) = params.position();
+
+                ui.horizontal(|ui| {
+                    ui.vertical(|ui| {
+                        ui.label("Top view (front is up)");
+                        top_view(ui, &params, setter, (x, y, z));
+                    });
+                    ui.vertical(|ui| {
+                        ui.label("Side view (front is right)");
+                        side_view(ui, &params, setter, (x, y, z));
+                    });
+                });
+
+                egui::Grid::new("params").num_columns(2).show(ui, |ui| {
+                    for param in [
+                        &params.azimuth,
+                        &params.elevation,
+                        &params.distance,
+                        &params.gain,
+                        &params.head_size,
+                    ] {
+                        ui.label(param.name());
+                        ui.add(widgets::ParamSlider::for_param(param, setter));
+                        ui.end_row();
+                    }
+                    ui.label(params.engine.name());
+                    ui.add(widgets::ParamSlider::for_param(&params.engine, setter));
+                    ui.end_row();
+                });
+
+                ui.separator();
+                headphone_eq_row(ui, &params, setter, &mut state.hp_eq_path, &hp_eq_error);
+                ui.separator();
+                draw_meters(ui, &meters);
+            });
+        },
+    )
+}
+
+fn preset_bar(ui: &mut egui::Ui, browser: &mut PresetBrowser, setter: &ParamSetter) {
+    let mut selected = None;
+    ui.horizontal(|ui| {
+        egui::ComboBox::from_id_source("preset")
+            .selected_text("Presets")
+            .show_ui(ui, |ui| {
+                ui.label("Factory");
+                for preset in &browser.factory {
+                    if ui.selectable_label(false, &preset.name).clicked() {
+                        selected = Some(preset.clone());
+                    }
+                }
+                ui.separator();
+                ui.label("User");
+                for preset in &browser.user {
+                    if ui.selectable_label(false, &preset.name).clicked() {
+                        selected = Some(preset.clone());
+                    }
+                }
+            });
+
+        ui.add(egui::TextEdit::singleline(&mut browser.name).hint_text("Name").desired_width(140.0));
+        if ui.add_enabled(!browser.name.trim().is_empty(), egui::Button::new("Save")).clicked() {
+            browser.save(setter);
+        }
+
+        ui.add(egui::TextEdit::singleline(&mut browser.file).hint_text("File").desired_width(200.0));
+        let has_file = !browser.file.trim().is_empty();
+        if ui.add_enabled(has_file, egui::Button::new("Import")).clicked() {
+            browser.import(setter);
+        }
+        if ui.add_enabled(has_file, egui::Button::new("Export")).clicked() {
+            browser.export(setter);
+        }
+    });
+
+    if let Some(preset) = selected {
+        browser.load(&preset, setter);
+    }
+    if let Some(message) = &browser.message {
+        ui.label(message);
+    }
+}
+
+/// The headphone EQ profile and its bypass. Loading or clearing a profile sets the persisted path
+/// through `set_state`, which reinitializes the plugin and with it the EQ, like loading a preset.
+fn headphone_eq_row(
+    ui: &mut egui::Ui,
+    params: &SpatializerParams,
+    setter: &ParamSetter,
+    path: &mut String,
+    error: &Mutex<Option<Error>>,
+) {
+    ui.horizontal(|ui| {
+        ui.label("Headphone EQ");
+        let edit = egui::TextEdit::singleline(path).hint_text(".wav or ParametricEQ.txt");
+        ui.add(edit.desired_width(240.0));
+        if ui.add_enabled(!path.trim().is_empty(), egui::Button::new("Load")).clicked() {
+            set_hp_eq_path(setter, Some(path.trim()));
+        }
+        let loaded = params.hp_eq_path.read().unwrap().is_some();
+        if ui.add_enabled(loaded, egui::Button::new("Clear")).clicked() {
+            path.clear();
+            set_hp_eq_path(setter, None);
+        }
+
+        let mut bypass = params.hp_eq_bypass.value();
+        if ui.checkbox(&mut bypass, "Bypass").changed() {
+            setter.begin_set_parameter(&params.hp_eq_bypass);
+            setter.set_parameter(&params.hp_eq_bypass, bypass);
+            setter.end_set_parameter(&params.hp_eq_bypass);
+        }
+    });
+
+    if let Some(err) = &*error.lock() {
+        ui.label(err.to_string());
+    }
+}
+
+fn set_hp_eq_path(setter: &ParamSetter, path: Option<&str>) {
+    let mut state = setter.raw_context.get_state();
+    // persistent fields are stored as JSON themselves
+    let value = serde_json::to_string(&path).unwrap_or_else(|_| String::from("null"));
+    state.fields.insert(String::from("headphone-eq-path"), value);
+    setter.raw_context.set_state(state);
+}
+
+/// Looking down on the listener, dragging moves the source in the horizontal plane and keeps its
+/// height.
+fn top_view(
+    ui: &mut egui::Ui,
+    params: &SpatializerParams,
+    setter: &ParamSetter,
+    (x, y, z): (f32, f32, f32),
+) {
+    let (response, painter) = ui.allocate_painter(Vec2::splat(PAD_SIZE), Sense::drag());
+    let rect = response.rect;
+    let scale = PAD_SIZE / 2.0 / PAD_RANGE;
+
+    // front is up, left is left
+    let to_screen = |x: f32, y: f32| rect.center() + clamp_to_pad(Vec2::new(-y, -x)) * scale;
+    draw_grid(&painter, rect);
+    draw_head(&painter, rect.center(), Vec2::new(0.0, -1.0));
+
+    drag_position(&response, setter, params, |pos| {
+        let offset = (pos - rect.center()) / scale;
+        (-offset.y, -offset.x, z)
+    });
+    painter.circle_filled(to_screen(x, y), 8.0, SOURCE);
+}
+
+/// Looking at the listener's left side, dragging moves the source front to back and up and down
+/// and keeps its left-right offset.
+fn side_view(
+    ui: &mut egui::Ui,
+    params: &SpatializerParams,
+    setter: &ParamSetter,
+    (x, y, z): (f32, f32, f32),
+) {
+    let (response, painter) = ui.allocate_painter(Vec2::splat(PAD_SIZE), Sense::drag());
+    let rect = response.rect;
+    let scale = PAD_SIZE / 2.0 / PAD_RANGE;
+
+    // front is right, up is up
+    let to_screen = |x: f32, z: f32| rect.center() + clamp_to_pad(Vec2::new(x, -z)) * scale;
+    draw_grid(&painter, rect);
+    draw_head(&painter, rect.center(), Vec2::new(1.0, 0.0));
+
+    drag_position(&response, setter, params, |pos| {
+        let offset = (pos - rect.center()) / scale;
+        (offset.x, y, -offset.y)
+    });
+    painter.circle_filled(to_screen(x, z), 8.0, SOURCE);
+}
+
+fn clamp_to_pad(offset: Vec2) -> Vec2 {
+    offset.clamp(Vec2::splat(-PAD_RANGE), Vec2::splat(PAD_RANGE))
+}
+
+/// Set the position parameters from the pointer while the pad is dragged, `to_position` converts
+/// the pointer position to SOFA cartesian coordinates.
+fn drag_position(
+    response: &egui::Response,
+    setter: &ParamSetter,
+    params: &SpatializerParams,
+    to_position: impl Fn(Pos2) -> (f32, f32, f32),
+) {
+    let position_params = [&params.azimuth, &params.elevation, &params.distance];
+
+    if response.drag_started() {
+        for param in position_params {
+            setter.begin_set_parameter(param);
+        }
+    }
+
+    if response.dragged() {
+        if let Some(pos) = response.interact_pointer_pos() {
+            let (x, y, z) = to_position(pos);
+            let (azimuth, elevation, distance) = cartesian_to_spherical(x, y, z);
+            setter.set_parameter(&params.azimuth, azimuth);
+            setter.set_parameter(&params.elevation, elevation);
+            setter.set_parameter(&params.distance, distance.clamp(MIN_DISTANCE, MAX_DISTANCE));
+        }
+    }
+
+    if response.drag_stopped() {
+        for param in position_params {
+            setter.end_set_parameter(param);
+        }
+    }
+}
+
+/// Peak and RMS bars for the input and both ears, then the interaural readouts
+fn draw_meters(ui: &mut egui::Ui, meters: &Meters) {
+    let load = |value: &nih_plug::prelude::AtomicF32| value.load(Ordering::Relaxed);
+
+    for (label, peak, rms) in [
+        ("In", &meters.input_peak, &meters.input_rms),
+        ("L", &meters.left_peak, &meters.left_rms),
+        ("R", &meters.right_peak, &meters.right_rms),
+    ] {
+        ui.horizontal(|ui| {
+            ui.label(format!("{label:>2}"));
+            level_bar(ui, load(peak), load(rms));
+            ui.label(format!("{:.1} dB", load(peak)));
+        });
+    }
+
+    ui.label(format!(
+        "ILD {:+.1} dB   Correlation {:+.2}",
+        load(&meters.ild),
+        load(&meters.correlation)
+    ));
+}
+
+/// -60..0 dBFS, the RMS as a filled bar and the peak as a line
+fn level_bar(ui: &mut egui::Ui, peak_db: f32, rms_db: f32) {
+    let (response, painter) = ui.allocate_painter(Vec2::new(400.0, 10.0), Sense::hover());
+    let rect = response.rect;
+    let x = |db: f32| rect.left() + rect.width() * ((db + 60.0) / 60.0).clamp(0.0, 1.0);
+
+    painter.rect_filled(rect, 2.0, BACKGROUND);
+    painter.rect_filled(
+        Rect::from_min_max(rect.left_top(), Pos2::new(x(rms_db), rect.bottom())),
+        2.0,
+        SOURCE,
+    );
+    painter.line_segment(
+        [Pos2::new(x(peak_db), rect.top()), Pos2::new(x(peak_db), rect.bottom())],
+        Stroke::new(2.0, HEAD),
+    );
+}
+
+/// Background with four rings out to `PAD_RANGE` and the axes
+fn draw_grid(painter: &egui::Painter, rect: Rect) {
+    let center = rect.center();
+    let scale = rect.width() / 2.0;
+    let stroke = Stroke::new(1.0, GRID);
+
+    painter.rect_filled(rect, 4.0, BACKGROUND);
+    for ring in 1..=4 {
+        painter.circle_stroke(center, scale * ring as f32 / 4.0, stroke);
+    }
+    painter.line_segment(
+        [Pos2::new(rect.left(), center.y), Pos2::new(rect.right(), center.y)],
+        stroke,
+    );
+    painter.line_segment(
+        [Pos2::new(center.x, rect.top()), Pos2::new(center.x, rect.bottom())],
+        stroke,
+    );
+    painter.text(
+        rect.left_top() + Vec2::splat(4.0),
+        Align2::LEFT_TOP,
+        format!("{PAD_RANGE} m"),
+        FontId::proportional(11.0),
+        GRID,
+    );
+}
+
+/// A head with a nose pointing in the `front` direction and ears to the sides
+fn draw_head(painter: &egui::Painter, center: Pos2, front: Vec2) {
+    let radius = 18.0;
+    let side = front.rot90();
+
+    painter.circle_filled(center, radius, HEAD);
+    painter.add(egui::Shape::convex_polygon(
+        vec![
+            center + front * (radius + 8.0),
+            center + front * (radius - 2.0) + side * 6.0,
+            center + front * (radius - 2.0) - side * 6.0,
+        ],
+        HEAD,
+        Stroke::NONE,
+    ));
+    // in the side view one ear faces the viewer, in the top view they stick out to both sides
+    if front.x == 0.0 {
+        painter.circle_filled(center + side * radius, 5.0, HEAD);
+        painter.circle_filled(center - side * radius, 5.0, HEAD);
+    } else {
+        painter.circle_stroke(center, 5.0, Stroke::new(2.0, BACKGROUND));
+    }
+}
@@ -0,0 +1,190 @@
+// Synthetic HRIR datasets for the tests and benchmarks, so they run without measured SOFA files.
+// Only built for tests and with the `fixtures` feature, which `cargo bench` needs.
+
+use crate::head_model;
+use crate::sofa_writer::HrirSet;
+use crate::spatializer_efx::Error;
+
+use std::path::{Path, PathBuf};
+
+/// A synthetic dataset of a rigid spherical head with the reference circumference, for
+/// benchmarks and tests that can't rely on measured datasets. Positions every 5 degrees of
+/// azimuth and 10 degrees of elevation at 1.5 m. Each IR is an impulse carrying the Woodworth
+/// ITD and a head shadow on the far ear, followed by a decaying pseudo-random tail standing in
+/// for the pinna and torso reflections. The result only depends on the arguments.
+pub fn spherical_head(sample_rate: f64, filter_len: usize) -> HrirSet {
+    let mut set = HrirSet::new(sample_rate, filter_len);
+    set.title = String::from("ASE Spatializer synthetic spherical head");
+
+    // common delay before the direct sound reaches the nearer ear
+    let onset = (filter_len / 16).min(16);
+    let mut seed: u32 = 1;
+    let mut noise = move || {
+        // numerical recipes LCG, reproducible across platforms and crate versions
+        seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+        (seed >> 8) as f32 / (1 << 23) as f32 - 1.0
+    };
+
+    let mut left = vec![0.0; filter_len];
+    let mut right = vec![0.0; filter_len];
+    for elevation in (-40..=90).step_by(10) {
+        // a single measurement at the pole
+        let azimuth_step = if elevation == 90 { 360 } else { 5 };
+        for azimuth in (0..360).step_by(azimuth_step) {
+            let (az, el) = ((azimuth as f32).to_radians(), (elevation as f32).to_radians());
+            let (x, y, z) = (az.cos() * el.cos(), az.sin() * el.cos(), el.sin());
+            let lateral = head_model::lateral_angle(x, y, z);
+            let itd = head_model::woodworth_itd(lateral, head_model::REFERENCE_CIRCUMFERENCE)
+                * sample_rate as f32;
+
+            // a positive ITD reaches the left ear first
+            let ears = [
+                (&mut left, (-itd).max(0.0), 0.5 + 0.3 * lateral.sin()),
+                (&mut right, itd.max(0.0), 0.5 - 0.3 * lateral.sin()),
+            ];
+            for (ir, delay, gain) in ears {
+                let delay = (onset + delay.round() as usize).min(filter_len - 1);
+                ir.fill(0.0);
+                ir[delay] = gain;
+                let mut decay = 0.3 * gain;
+                for tap in &mut ir[delay + 1..] {
+                    *tap = decay * noise();
+                    decay *= 0.9;
+                }
+            }
+
+            set.push([azimuth as f64, elevation as f64, 1.5], &left, &right);
+        }
+    }
+
+    set
+}
+
+/// The HRIR sphere in `fixtures/octahedron_sphere.bin`, in the `.bin` format of the `hrtf` crate:
+/// the magic `HRIR`, then the sample rate, HRIR length, vertex count and index count as
+/// little-endian `u32`s, the triangle indices, and per vertex its position followed by the left
+/// and the right HRIR as `f32`s. Positions are in the crate's coordinates (+x right, +y up, +z
+/// front). The six vertices of an octahedron carry 32 sample HRIRs that are single impulses 8
+/// samples in, apart from the left and right vertices where the near ear is 5 and the far ear 11
+/// samples in.
+pub fn octahedron_sphere() -> Vec<u8> {
+    const LEN: usize = 32;
+    let vertices: [[f32; 3]; 6] = [
+        [1.0, 0.0, 0.0],
+        [-1.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0],
+        [0.0, -1.0, 0.0],
+        [0.0, 0.0, 1.0],
+        [0.0, 0.0, -1.0],
+    ];
+    let faces: [[u32; 3]; 8] = [
+        [0, 2, 4],
+        [0, 5, 2],
+        [0, 4, 3],
+        [0, 3, 5],
+        [1, 4, 2],
+        [1, 2, 5],
+        [1, 3, 4],
+        [1, 5, 3],
+    ];
+    let impulse = |at: usize, gain: f32| {
+        let mut ir = [0.0f32; LEN];
+        ir[at] = gain;
+        ir
+    };
+
+    let mut bytes = b"HRIR".to_vec();
+    for value in [48000, LEN as u32, vertices.len() as u32, 3 * faces.len() as u32] {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    for index in faces.iter().flatten() {
+        bytes.extend_from_slice(&index.to_le_bytes());
+    }
+    for position in vertices {
+        let (left, right) = match position[0] {
+            x if x > 0.0 => (impulse(11, 0.2), impulse(5, 0.8)),
+            x if x < 0.0 => (impulse(5, 0.8), impulse(11, 0.2)),
+            _ => (impulse(8, 0.5), impulse(8, 0.5)),
+        };
+        for value in position.iter().chain(&left).chain(&right) {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    bytes
+}
+
+/// A dataset written to a SOFA file in the temp directory, removed again when this is dropped.
+pub struct TempSofa {
+    path: PathBuf,
+}
+
+impl TempSofa {
+    /// `name` keeps the files of tests running in parallel apart.
+    pub fn new(name: &str, set: &HrirSet) -> Result<Self, Error> {
+        let path = std::env::temp_dir().join(format!(
+            "ase-project-{}-{name}.sofa",
+            std::process::id()
+        ));
+        set.write(&path)?;
+
+        Ok(TempSofa { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempSofa {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spherical_head() {
+        let set = spherical_head(48000.0, 128);
+        assert_eq!(set.measurements(), 13 * 72 + 1);
+
+        let onset = |ir: &[f64]| ir.iter().position(|s| *s != 0.0).unwrap();
+        let peak = |ir: &[f64]| ir.iter().fold(0.0f64, |peak, s| peak.max(s.abs()));
+        let find = |az: f64, el: f64| {
+            let m = set.source_positions.iter().position(|p| p[0] == az && p[1] == el);
+            m.unwrap()
+        };
+
+        // straight ahead both ears are the same, to the left the left ear is earlier and louder
+        let front = find(0.0, 0.0);
+        assert_eq!(onset(set.ir(front, 0)), onset(set.ir(front, 1)));
+        let left = find(90.0, 0.0);
+        assert!(onset(set.ir(left, 0)) + 25 < onset(set.ir(left, 1)));
+        assert!(peak(set.ir(left, 0)) > 3.0 * peak(set.ir(left, 1)));
+
+        // deterministic
+        assert_eq!(set.data_ir, spherical_head(48000.0, 128).data_ir);
+    }
+
+    #[test]
+    fn test_octahedron_sphere_fixture() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/octahedron_sphere.bin");
+        if std::env::var_os("ASE_UPDATE_GOLDEN").is_some() {
+            std::fs::write(&path, octahedron_sphere()).unwrap();
+        }
+        let fixture = std::fs::read(&path).unwrap();
+        assert!(fixture == octahedron_sphere(), "run with ASE_UPDATE_GOLDEN=1 to regenerate it");
+    }
+
+    #[test]
+    fn test_temp_sofa_removed() {
+        let sofa = TempSofa::new("temp-sofa", &spherical_head(48000.0, 32)).unwrap();
+        let path = sofa.path().to_path_buf();
+        assert_eq!(HrirSet::read(&path).unwrap().measurements(), 13 * 72 + 1);
+
+        drop(sofa);
+        assert!(!path.exists());
+    }
+}
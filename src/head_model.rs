@@ -0,0 +1,192 @@
+// Head-size personalisation for non-individual HRIRs (e.g. the KU100 dummy head)
+// The ITD embedded in the measured IRs is rescaled with the Woodworth spherical head model
+
+use sofar::reader::Filter;
+use std::f32::consts::PI;
+
+/// Speed of sound used by the Woodworth model, in m/s.
+pub const SPEED_OF_SOUND: f32 = 343.0;
+/// The head circumference the measured dataset is assumed to correspond to, in cm. This is the
+/// 8.75 cm Woodworth radius that is commonly used for dummy heads like the KU100.
+pub const REFERENCE_CIRCUMFERENCE: f32 = 55.0;
+/// The smallest and largest head circumference the user can dial in, in cm.
+pub const MIN_CIRCUMFERENCE: f32 = 45.0;
+pub const MAX_CIRCUMFERENCE: f32 = 65.0;
+
+/// Woodworth ITD in seconds for a source at `lateral` radians (positive = left) around a head with
+/// the given circumference in cm. Positive values mean that the sound reaches the left ear first.
+pub fn woodworth_itd(lateral: f32, circumference: f32) -> f32 {
+    let radius = circumference / 100.0 / (2.0 * PI);
+    let lateral = lateral.clamp(-PI / 2.0, PI / 2.0);
+
+    radius / SPEED_OF_SOUND * (lateral + lateral.sin())
+}
+
+/// Lateral angle in radians of the direction `(x, y, z)` in SOFA coordinates (+y is left).
+pub fn lateral_angle(x: f32, y: f32, z: f32) -> f32 {
+    let norm = (x * x + y * y + z * z).sqrt();
+    if norm <= f32::EPSILON {
+        return 0.0;
+    }
+
+    (y / norm).clamp(-1.0, 1.0).asin()
+}
+
+/// Rescales the interaural cues of the filters returned by `Sofar::filter` to a different head size.
+pub struct HeadModel {
+    sample_rate: f32,
+    /// Where the direct sound starts in every IR, the pinna stretch is anchored here
+    onset: f32,
+    /// Copy of the IR that is being rewritten, allocated once so `apply` can run on the audio thread.
+    scratch: Vec<f32>,
+}
+
+impl HeadModel {
+    pub fn new(filt_len: usize, sample_rate: f32) -> Self {
+        HeadModel {
+            sample_rate,
+            onset: 0.0,
+            scratch: vec![0.0; filt_len],
+        }
+    }
+
+    /// Anchor the pinna stretch at the dataset's common pre-delay in samples, so the direct sound
+    /// arrives at the same time for every head size and the latency reported to the host holds.
+    pub fn set_onset(&mut self, onset: usize) {
+        self.onset = onset as f32;
+    }
+
+    /// Personalise `filter`, which was looked up at `(x, y, z)`, for a head with `circumference` cm.
+    ///
+    /// Without `pinna_shift` only the ITD is changed by delaying the lagging or leading ear. With
+    /// `pinna_shift` both IRs are stretched in time from the onset on instead, which moves the pinna
+    /// notches down for bigger heads (and up for smaller ones) and scales the embedded ITD by the
+    /// same ratio.
+    pub fn apply(
+        &mut self,
+        x: f32,
+        y: f32,
+        z: f32,
+        circumference: f32,
+        pinna_shift: bool,
+        filter: &mut Filter,
+    ) {
+        let circumference = circumference.clamp(MIN_CIRCUMFERENCE, MAX_CIRCUMFERENCE);
+        if (circumference - REFERENCE_CIRCUMFERENCE).abs() < 1e-3 {
+            return;
+        }
+
+        if pinna_shift {
+            let stretch = circumference / REFERENCE_CIRCUMFERENCE;
+            self.stretch(&mut filter.left, stretch);
+            self.stretch(&mut filter.right, stretch);
+            return;
+        }
+
+        let lateral = lateral_angle(x, y, z);
+        let delta = woodworth_itd(lateral, circumference)
+            - woodworth_itd(lateral, REFERENCE_CIRCUMFERENCE);
+        let delta_samples = delta * self.sample_rate;
+
+        // A growing ITD for a source on the left means the right ear has to arrive later, and vice
+        // versa. Only ever delay so that no part of the IR gets cut off at the start.
+        if delta_samples > 0.0 {
+            self.delay(&mut filter.right, delta_samples);
+        } else {
+            self.delay(&mut filter.left, -delta_samples);
+        }
+    }
+
+    /// Delay `ir` by a fractional number of samples using linear interpolation.
+    fn delay(&mut self, ir: &mut [f32], samples: f32) {
+        if samples <= 0.0 {
+            return;
+        }
+
+        let scratch = &mut self.scratch[..ir.len()];
+        scratch.copy_from_slice(ir);
+        for (n, out) in ir.iter_mut().enumerate() {
+            *out = interpolate(scratch, n as f32 - samples);
+        }
+    }
+
+    /// Stretch `ir` in time around the onset by `factor`, a factor larger than 1 makes the response
+    /// longer.
+    fn stretch(&mut self, ir: &mut [f32], factor: f32) {
+        let onset = self.onset;
+        let scratch = &mut self.scratch[..ir.len()];
+        scratch.copy_from_slice(ir);
+        for (n, out) in ir.iter_mut().enumerate() {
+            *out = interpolate(scratch, onset + (n as f32 - onset) / factor);
+        }
+    }
+}
+
+/// Linearly interpolated read from `ir` at fractional position `pos`, zero outside of the IR.
+fn interpolate(ir: &[f32], pos: f32) -> f32 {
+    if pos < 0.0 {
+        return 0.0;
+    }
+
+    let idx = pos.floor() as usize;
+    let frac = pos - idx as f32;
+    let a = ir.get(idx).copied().unwrap_or(0.0);
+    let b = ir.get(idx + 1).copied().unwrap_or(0.0);
+
+    a + (b - a) * frac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    #[test]
+    fn test_woodworth() {
+        assert_approx_eq!(woodworth_itd(0.0, REFERENCE_CIRCUMFERENCE), 0.0);
+
+        // Roughly 650 us for a source at 90 degrees with the reference head
+        let itd = woodworth_itd(PI / 2.0, REFERENCE_CIRCUMFERENCE);
+        assert!(itd > 600e-6 && itd < 700e-6);
+        assert_approx_eq!(woodworth_itd(-PI / 2.0, REFERENCE_CIRCUMFERENCE), -itd);
+    }
+
+    #[test]
+    fn test_bigger_head_delays_far_ear() {
+        let mut filter = Filter::new(64);
+        filter.left[4] = 1.0;
+        filter.right[10] = 1.0;
+
+        // Source on the left, a bigger head should delay the right ear by roughly 6 samples
+        let mut head = HeadModel::new(64, 48000.0);
+        head.apply(0.0, 1.0, 0.0, 65.0, false, &mut filter);
+
+        assert_approx_eq!(filter.left[4], 1.0);
+        let peak = filter
+            .right
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .unwrap()
+            .0;
+        assert!(peak > 10 && peak <= 16);
+    }
+
+    #[test]
+    fn test_stretch_keeps_onset() {
+        let mut filter = Filter::new(64);
+        filter.left[20] = 1.0;
+        filter.left[31] = 0.5;
+        filter.right[20] = 1.0;
+
+        // a smaller head pulls the later reflection in, the direct sound stays put
+        let mut head = HeadModel::new(64, 48000.0);
+        head.set_onset(20);
+        head.apply(1.0, 0.0, 0.0, 45.0, true, &mut filter);
+
+        assert_approx_eq!(filter.left[20], 1.0);
+        assert_approx_eq!(filter.right[20], 1.0);
+        assert!(filter.left[..20].iter().all(|s| *s == 0.0));
+        assert_approx_eq!(filter.left[29], 0.5, 1e-4);
+    }
+}
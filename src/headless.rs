@@ -0,0 +1,521 @@
+// A headless host for testing `Spatializer` as a plugin without a DAW. It initializes the plugin
+// the way nih_plug's wrappers do, feeds it synthetic buffers split at scripted parameter changes
+// like sample-accurate automation, sets the parameters through their `ParamPtr`s like a host
+// event and renders every block with `process_block`, the body of `process`, under the same
+// allocation check the wrappers use in debug builds.
+
+use assert_no_alloc::assert_no_alloc;
+use nih_plug::prelude::*;
+use nih_plug::wrapper::state::ParamValue;
+
+use crate::fixtures::{self, TempSofa};
+use crate::head_model;
+use crate::presets;
+use crate::spatializer_efx::{
+    ConvolutionEngine, CONTROL_LEN, MAX_DISTANCE, MIN_DISTANCE, PARTITION_LEN,
+};
+use crate::trajectory::{NoteDivision, Shape, TransportState};
+use crate::{BlockHost, Spatializer, SpatializerParams};
+
+use std::cell::Cell;
+
+const SAMPLE_RATE: f32 = 48000.0;
+const MAX_BUFFER_SIZE: usize = 512;
+const FILTER_LEN: usize = 256;
+
+/// A parameter change as the host would send it.
+#[derive(Debug, Clone, Copy)]
+enum Change {
+    Azimuth(f32),
+    Elevation(f32),
+    Distance(f32),
+    /// Linear gain, smoothed like host automation of the gain parameter
+    Gain(f32),
+    HeadSize(f32),
+    Engine(ConvolutionEngine),
+    Motion(Shape),
+    MotionSync(bool),
+    MotionDivision(NoteDivision),
+}
+
+impl Change {
+    /// The parameter this changes and its new normalized value, what a host event carries.
+    fn normalized(self, params: &SpatializerParams) -> (ParamPtr, f32) {
+        match self {
+            Change::Azimuth(azimuth) => (
+                params.azimuth.as_ptr(),
+                params.azimuth.preview_normalized(azimuth),
+            ),
+            Change::Elevation(elevation) => (
+                params.elevation.as_ptr(),
+                params.elevation.preview_normalized(elevation),
+            ),
+            Change::Distance(distance) => (
+                params.distance.as_ptr(),
+                params.distance.preview_normalized(distance),
+            ),
+            Change::Gain(gain) => (params.gain.as_ptr(), params.gain.preview_normalized(gain)),
+            Change::HeadSize(head_size) => (
+                params.head_size.as_ptr(),
+                params.head_size.preview_normalized(head_size),
+            ),
+            Change::Engine(engine) => (
+                params.engine.as_ptr(),
+                params.engine.preview_normalized(engine),
+            ),
+            Change::Motion(motion) => (
+                params.motion.as_ptr(),
+                params.motion.preview_normalized(motion),
+            ),
+            Change::MotionSync(sync) => (
+                params.motion_sync.as_ptr(),
+                params.motion_sync.preview_normalized(sync),
+            ),
+            Change::MotionDivision(division) => (
+                params.motion_division.as_ptr(),
+                params.motion_division.preview_normalized(division),
+            ),
+        }
+    }
+}
+
+/// `change` takes effect at sample `at` of the rendered signal.
+#[derive(Debug, Clone, Copy)]
+struct Automation {
+    at: usize,
+    change: Change,
+}
+
+/// The host side of `initialize` and `process`, with the transport the plugin sees and the
+/// latency it reported last.
+#[derive(Default)]
+struct HostContext {
+    transport: TransportState,
+    latency: Cell<u32>,
+}
+
+impl InitContext<Spatializer> for HostContext {
+    fn plugin_api(&self) -> PluginApi {
+        PluginApi::Standalone
+    }
+
+    fn execute(&self, _task: ()) {}
+
+    fn set_latency_samples(&self, samples: u32) {
+        self.latency.set(samples);
+    }
+
+    fn set_current_voice_capacity(&self, _capacity: u32) {}
+}
+
+impl BlockHost for HostContext {
+    fn transport(&self) -> TransportState {
+        self.transport
+    }
+
+    fn set_latency_samples(&self, samples: u32) {
+        self.latency.set(samples);
+    }
+}
+
+struct HeadlessHost {
+    plugin: Spatializer,
+    context: HostContext,
+}
+
+impl HeadlessHost {
+    /// An initialized and reset plugin rendering the synthetic spherical head.
+    fn new(name: &str) -> Self {
+        let set = fixtures::spherical_head(SAMPLE_RATE as f64, FILTER_LEN);
+        let file = TempSofa::new(&format!("headless-{name}"), &set).unwrap();
+        let mut plugin = Spatializer::default();
+        *plugin.params.sofa_path.write().unwrap() = Some(file.path().display().to_string());
+
+        let buffer_config = BufferConfig {
+            sample_rate: SAMPLE_RATE,
+            min_buffer_size: None,
+            max_buffer_size: MAX_BUFFER_SIZE as u32,
+            process_mode: ProcessMode::Realtime,
+        };
+        let mut context = HostContext::default();
+        let layout = &Spatializer::AUDIO_IO_LAYOUTS[0];
+        assert!(plugin.initialize(layout, &buffer_config, &mut context));
+        assert!(plugin.efx.is_some(), "{:?}", plugin.sofa_error.lock());
+
+        // the wrappers reset the smoothers to the current values before processing
+        for (_, param, _) in plugin.params.param_map() {
+            unsafe { param.update_smoother(SAMPLE_RATE, true) };
+        }
+        plugin.reset();
+
+        HeadlessHost { plugin, context }
+    }
+
+    /// The latency the host was told about last.
+    fn latency(&self) -> u32 {
+        self.context.latency.get()
+    }
+
+    /// Set a parameter like the wrappers do for an automation event between two blocks.
+    fn apply(&mut self, change: Change) {
+        let (param, normalized) = change.normalized(&self.plugin.params);
+        unsafe {
+            param.set_normalized_value(normalized);
+            param.update_smoother(SAMPLE_RATE, false);
+        }
+    }
+
+    /// Render a stereo signal in host blocks of the sizes in `host_blocks` (repeated as needed),
+    /// split further at every automation point like nih_plug does for sample-accurate automation.
+    fn process(
+        &mut self,
+        input: [&[f32]; 2],
+        automation: &[Automation],
+        host_blocks: &[usize],
+    ) -> [Vec<f32>; 2] {
+        let len = input[0].len();
+        let mut output = [input[0].to_vec(), input[1].to_vec()];
+
+        // reserve the channel pointers now, `set_slices` must not allocate while processing
+        let mut buffer = Buffer::default();
+        unsafe {
+            buffer.set_slices(0, |slices| {
+                slices.push(&mut []);
+                slices.push(&mut []);
+            });
+        }
+
+        let [mut left, mut right] = output.each_mut().map(|channel| &mut channel[..]);
+        let mut automation = automation.iter().peekable();
+        let mut host_blocks = host_blocks.iter().cycle();
+        let mut host_block_end = 0;
+        let mut pos = 0;
+        while pos < len {
+            while let Some(point) = automation.next_if(|point| point.at <= pos) {
+                self.apply(point.change);
+            }
+            if pos == host_block_end {
+                host_block_end = pos + (*host_blocks.next().unwrap()).min(MAX_BUFFER_SIZE);
+            }
+            let mut end = host_block_end.min(len);
+            if let Some(point) = automation.peek() {
+                end = end.min(point.at);
+            }
+
+            let (block_left, rest) = std::mem::take(&mut left).split_at_mut(end - pos);
+            left = rest;
+            let (block_right, rest) = std::mem::take(&mut right).split_at_mut(end - pos);
+            right = rest;
+            unsafe {
+                buffer.set_slices(end - pos, |slices| {
+                    slices.clear();
+                    slices.push(block_left);
+                    slices.push(block_right);
+                });
+            }
+
+            let (plugin, context) = (&mut self.plugin, &self.context);
+            assert_no_alloc(|| plugin.process_block(&mut buffer, context));
+
+            let transport = &mut self.context.transport;
+            if transport.playing {
+                let beats = (end - pos) as f64 / SAMPLE_RATE as f64 * transport.tempo / 60.0;
+                transport.pos_beats = transport.pos_beats.map(|pos| pos + beats);
+            }
+            pos = end;
+        }
+
+        output
+    }
+}
+
+fn noise(len: usize, seed: u64) -> Vec<f32> {
+    use rand::prelude::*;
+    let mut rng = StdRng::seed_from_u64(seed);
+    (0..len).map(|_| rng.gen_range(-0.5..0.5)).collect()
+}
+
+fn energy(samples: &[f32]) -> f32 {
+    samples.iter().map(|s| s * s).sum()
+}
+
+/// No NaNs, infinities or denormals anywhere in the output.
+fn assert_clean(output: &[Vec<f32>; 2]) {
+    for (ear, channel) in output.iter().enumerate() {
+        for (n, sample) in channel.iter().enumerate() {
+            assert!(sample.is_finite(), "{sample} in channel {ear} at sample {n}");
+            assert!(!sample.is_subnormal(), "denormal in channel {ear} at sample {n}");
+        }
+    }
+}
+
+#[test]
+fn test_initialize_and_latency() {
+    let mut host = HeadlessHost::new("latency");
+    let efx_latency = host.plugin.efx.as_ref().unwrap().latency_samples();
+    assert_eq!(host.latency(), efx_latency);
+
+    // switching the engine changes the latency from the next block on
+    let input = noise(4096, 1);
+    let automation = [Automation {
+        at: 1000,
+        change: Change::Engine(ConvolutionEngine::OverlapAdd),
+    }];
+    let output = host.process([&input, &input], &automation, &[MAX_BUFFER_SIZE]);
+    assert_clean(&output);
+    assert_eq!(host.latency(), efx_latency + PARTITION_LEN as u32);
+
+    // a reset drops the convolution tail
+    host.plugin.reset();
+    let silence = vec![0.0; 2048];
+    let output = host.process([&silence, &silence], &[], &[MAX_BUFFER_SIZE]);
+    assert!(output.iter().flatten().all(|s| *s == 0.0));
+}
+
+#[test]
+fn test_sample_accurate_automation() {
+    let input = noise(16384, 2);
+    let switch = 8191;
+    let automation = [
+        Automation {
+            at: 0,
+            change: Change::Azimuth(90.0),
+        },
+        Automation {
+            at: switch,
+            change: Change::Azimuth(-90.0),
+        },
+    ];
+
+    let mut host = HeadlessHost::new("sample-accurate");
+    let output = host.process([&input, &input], &automation, &[MAX_BUFFER_SIZE, 100, 333, 1]);
+    assert_clean(&output);
+
+    // left up to the change, right once the tail of the left position has rung out
+    let before = switch - 4096..switch;
+    let after = switch + host.latency() as usize + FILTER_LEN..switch + 4096;
+    let [left, right] = &output;
+    assert!(energy(&left[before.clone()]) > 4.0 * energy(&right[before]));
+    assert!(energy(&right[after.clone()]) > 4.0 * energy(&left[after]));
+
+    // the same automation in other host block sizes gives the same output
+    let mut host = HeadlessHost::new("sample-accurate-blocks");
+    let blocks = host.process([&input, &input], &automation, &[64]);
+    for (a, b) in output.iter().flatten().zip(blocks.iter().flatten()) {
+        assert!((a - b).abs() < 1e-4);
+    }
+}
+
+#[test]
+fn test_extreme_automation_stays_clean() {
+    // a burst followed by silence, so the tails decay while the parameters keep moving
+    let mut input = noise(48000, 3);
+    input[24000..].fill(0.0);
+
+    let mut automation = Vec::new();
+    let changes = [
+        Change::Distance(MIN_DISTANCE),
+        Change::Elevation(90.0),
+        Change::Azimuth(180.0),
+        Change::Elevation(-90.0),
+        Change::Azimuth(-180.0),
+        Change::HeadSize(head_model::MIN_CIRCUMFERENCE),
+        Change::Distance(MAX_DISTANCE),
+        Change::Motion(Shape::Circle),
+        Change::Gain(util::db_to_gain(30.0)),
+        Change::HeadSize(head_model::MAX_CIRCUMFERENCE),
+        Change::Engine(ConvolutionEngine::OverlapAdd),
+        Change::Motion(Shape::RandomWalk),
+        Change::Engine(ConvolutionEngine::Sofar),
+        Change::Gain(util::db_to_gain(-30.0)),
+    ];
+    for (i, change) in changes.into_iter().enumerate() {
+        automation.push(Automation {
+            at: 1 + i * 3001,
+            change,
+        });
+    }
+
+    let mut host = HeadlessHost::new("extreme");
+    let output = host.process([&input, &input], &automation, &[MAX_BUFFER_SIZE, 17]);
+    assert_clean(&output);
+}
+
+#[test]
+fn test_continuous_under_automation() {
+    // a sine moving around the front in 1 degree steps while the gain ramps, as a host sends a
+    // drawn automation curve
+    let input: Vec<f32> = (0..24000)
+        .map(|n| 0.5 * (2.0 * std::f32::consts::PI * 220.0 * n as f32 / SAMPLE_RATE).sin())
+        .collect();
+    let mut automation: Vec<Automation> = (0..=90)
+        .map(|step| Automation {
+            at: 4096 + step * 128,
+            change: Change::Azimuth(-45.0 + step as f32),
+        })
+        .collect();
+    automation.insert(
+        0,
+        Automation {
+            at: 2048,
+            change: Change::Gain(util::db_to_gain(-6.0)),
+        },
+    );
+    automation.sort_by_key(|point| point.at);
+
+    let mut host = HeadlessHost::new("continuous");
+    let output = host.process([&input, &input], &automation, &[MAX_BUFFER_SIZE, 77]);
+    assert_clean(&output);
+
+    // no step between neighbouring samples much larger than the sine's own once it settled
+    let max_step = |samples: &[f32]| {
+        samples
+            .windows(2)
+            .fold(0.0f32, |max, pair| max.max((pair[1] - pair[0]).abs()))
+    };
+    for channel in &output {
+        let settled = max_step(&channel[FILTER_LEN + PARTITION_LEN..2048]);
+        let overall = max_step(&channel[FILTER_LEN + PARTITION_LEN..]);
+        assert!(overall < 3.0 * settled, "step of {overall}, {settled} without automation");
+    }
+}
+
+#[test]
+fn test_motion_follows_transport() {
+    let mut host = HeadlessHost::new("transport");
+    let automation = [
+        Change::Motion(Shape::Circle),
+        Change::MotionSync(true),
+        Change::MotionDivision(NoteDivision::Bar),
+    ]
+    .map(|change| Automation { at: 0, change });
+    let silence = vec![0.0; 48000];
+    let azimuth = |host: &HeadlessHost| host.plugin.efx.as_ref().unwrap().rendered_position().0;
+
+    // stopped, the source holds still
+    host.process([&silence, &silence], &automation, &[MAX_BUFFER_SIZE]);
+    assert_eq!(azimuth(&host), 0.0);
+
+    // a bar of 3/4 at 120 BPM lasts 1.5 s, the trajectory moves on at the end of every control
+    // block
+    host.context.transport = TransportState {
+        playing: true,
+        tempo: 120.0,
+        beats_per_bar: 3.0,
+        ..TransportState::default()
+    };
+    host.process([&silence, &silence], &[], &[MAX_BUFFER_SIZE]);
+    let expected = (48000.0 - CONTROL_LEN as f32) / 72000.0 * 360.0;
+    let travelled = (azimuth(&host) - expected).rem_euclid(360.0);
+    assert!(travelled.min(360.0 - travelled) < 1e-2, "{}", azimuth(&host));
+}
+
+#[test]
+fn test_fly_by_preset_changes_distance() {
+    let mut host = HeadlessHost::new("fly-by");
+    let preset = presets::factory().into_iter().find(|p| p.name == "Fly-By").unwrap();
+    for (id, param, _) in host.plugin.params.param_map() {
+        let plain = match preset.params.get(&id) {
+            Some(ParamValue::F32(value)) => *value,
+            Some(ParamValue::I32(value)) => *value as f32,
+            Some(ParamValue::Bool(value)) => *value as u8 as f32,
+            _ => continue,
+        };
+        unsafe {
+            param.set_normalized_value(param.preview_normalized(plain));
+            param.update_smoother(SAMPLE_RATE, true);
+        }
+    }
+
+    // one full pass there and back, the Doppler shift needs the distance to change
+    let silence = vec![0.0; 12000];
+    let mut distances = Vec::new();
+    for _ in 0..20 {
+        host.process([&silence, &silence], &[], &[MAX_BUFFER_SIZE]);
+        distances.push(host.plugin.efx.as_ref().unwrap().rendered_position().2);
+    }
+    let closest = distances.iter().copied().fold(f32::INFINITY, f32::min);
+    let furthest = distances.iter().copied().fold(0.0, f32::max);
+    assert!(closest < 1.1 && furthest > 5.0, "{distances:?}");
+}
+
+/// Render noise through the plugin from `azimuth` and `elevation`, set like host automation, and
+/// measure the interaural cues: the ITD in samples from the peak of the cross-correlation,
+/// positive when the left ear hears the source first, and the broadband ILD in dB, positive when
+/// the left ear is louder.
+fn interaural_cues(host: &mut HeadlessHost, azimuth: f32, elevation: f32) -> (i32, f32) {
+    const MAX_LAG: i32 = 48;
+    let input = noise(8192, 5);
+    let automation = [Change::Azimuth(azimuth), Change::Elevation(elevation)]
+        .map(|change| Automation { at: 0, change });
+    host.plugin.reset();
+    let [left, right] = host.process([&input, &input], &automation, &[MAX_BUFFER_SIZE]);
+
+    // skip the onset, the convolution isn't in its steady state yet
+    let (left, right) = (&left[1024..], &right[1024..]);
+    let correlation = |lag: i32| -> f32 {
+        let len = left.len() as i32 - MAX_LAG;
+        (MAX_LAG..len)
+            .map(|n| left[n as usize] * right[(n + lag) as usize])
+            .sum()
+    };
+    let itd = (-MAX_LAG..=MAX_LAG)
+        .max_by(|&a, &b| correlation(a).total_cmp(&correlation(b)))
+        .unwrap();
+    let ild = 10.0 * (energy(left) / energy(right)).log10();
+
+    (itd, ild)
+}
+
+#[test]
+fn test_interaural_cues_per_azimuth() {
+    let mut host = HeadlessHost::new("interaural-azimuth");
+
+    // from the right through the front to the left, both cues grow towards the left
+    let mut previous = (i32::MIN, f32::MIN);
+    for azimuth in (-90i32..=90).step_by(15) {
+        let (itd, ild) = interaural_cues(&mut host, azimuth as f32, 0.0);
+
+        match azimuth {
+            0 => {
+                assert_eq!(itd, 0);
+                assert!(ild.abs() < 0.5, "ILD {ild} dB straight ahead");
+            }
+            _ => {
+                assert_eq!(itd.signum(), azimuth.signum(), "ITD {itd} at {azimuth} deg");
+                assert_eq!(ild.signum() as i32, azimuth.signum(), "ILD {ild} at {azimuth} deg");
+            }
+        }
+        assert!(itd >= previous.0, "ITD {itd} at {azimuth} deg after {}", previous.0);
+        assert!(ild > previous.1, "ILD {ild} at {azimuth} deg after {}", previous.1);
+        previous = (itd, ild);
+    }
+
+    // fully lateral, within a sample of the Woodworth ITD of the dataset's head
+    let lateral = std::f32::consts::FRAC_PI_2;
+    let woodworth = head_model::woodworth_itd(lateral, head_model::REFERENCE_CIRCUMFERENCE);
+    let (itd, _) = interaural_cues(&mut host, 90.0, 0.0);
+    assert!((itd as f32 - woodworth * SAMPLE_RATE).abs() <= 1.0, "ITD {itd} at the left ear");
+}
+
+#[test]
+fn test_interaural_cues_front_back_and_elevation() {
+    let mut host = HeadlessHost::new("interaural-front-back");
+
+    // behind the listener the lateral cues keep their side and mirror the front
+    for (front, back) in [(30.0, 150.0), (60.0, 120.0), (-45.0, -135.0)] {
+        let (front_itd, front_ild) = interaural_cues(&mut host, front, 0.0);
+        let (back_itd, back_ild) = interaural_cues(&mut host, back, 0.0);
+        assert!((front_itd - back_itd).abs() <= 1, "{front} vs {back} deg");
+        assert!((front_ild - back_ild).abs() < 1.0, "{front} vs {back} deg");
+    }
+
+    // towards the pole the lateral cues vanish
+    let mut previous = i32::MAX;
+    for elevation in (0..=90).step_by(30) {
+        let (itd, _) = interaural_cues(&mut host, 90.0, elevation as f32);
+        assert!(itd < previous || itd == 0, "ITD {itd} at {elevation} deg elevation");
+        previous = itd;
+    }
+    assert_eq!(previous, 0);
+}
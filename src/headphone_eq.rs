@@ -0,0 +1,503 @@
+// Headphone compensation applied to the binaural output, either an inverse FIR filter stored as a
+// WAV file or an AutoEQ style parametric EQ profile (ParametricEQ.txt)
+
+use hound::{SampleFormat, WavReader};
+use realfft::RealFftPlanner;
+
+use crate::spatializer_efx::Error;
+
+use std::f64::consts::PI;
+use std::path::Path;
+
+/// Longer inverse filters are truncated, the FIR stage convolves in the time domain.
+pub const MAX_FIR_LEN: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BandType {
+    Peak,
+    LowShelf,
+    HighShelf,
+    LowPass,
+    HighPass,
+}
+
+/// One `Filter N: ON PK Fc 105 Hz Gain -2.3 dB Q 0.70` line of a parametric profile.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Band {
+    pub band_type: BandType,
+    pub freq: f32,
+    pub gain_db: f32,
+    pub q: f32,
+}
+
+#[derive(Debug, Clone)]
+pub enum EqProfile {
+    /// Inverse filters for the left and right ear
+    Fir {
+        left: Vec<f32>,
+        right: Vec<f32>,
+        sample_rate: u32,
+        preamp_db: f32,
+    },
+    Parametric { bands: Vec<Band>, preamp_db: f32 },
+}
+
+impl EqProfile {
+    /// Load a `.wav` inverse filter or a parametric EQ text file, depending on the extension.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let is_wav = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("wav"));
+
+        let profile = if is_wav {
+            Self::load_fir(path)
+        } else {
+            std::fs::read_to_string(path)
+                .map_err(|err| err.to_string())
+                .and_then(|text| Self::parse_parametric(&text))
+        };
+
+        profile.map_err(|reason| Error::EqProfile {
+            path: path.display().to_string(),
+            reason,
+        })
+    }
+
+    fn load_fir(path: &Path) -> Result<Self, String> {
+        let mut reader = WavReader::open(path).map_err(|err| err.to_string())?;
+        let spec = reader.spec();
+        let channels = spec.channels as usize;
+        if channels == 0 || channels > 2 {
+            return Err(format!("expected a mono or stereo filter, got {channels} channels"));
+        }
+
+        let samples: Vec<f32> = match spec.sample_format {
+            SampleFormat::Float => reader.samples::<f32>().collect::<Result<_, _>>(),
+            SampleFormat::Int => {
+                let scale = 1.0 / (1i64 << (spec.bits_per_sample - 1)) as f32;
+                reader
+                    .samples::<i32>()
+                    .map(|s| s.map(|s| s as f32 * scale))
+                    .collect::<Result<_, _>>()
+            }
+        }
+        .map_err(|err| err.to_string())?;
+
+        let mut left: Vec<f32> = samples.iter().step_by(channels).copied().collect();
+        let mut right: Vec<f32> = samples.iter().skip(channels - 1).step_by(channels).copied().collect();
+        if left.is_empty() {
+            return Err(String::from("the filter is empty"));
+        }
+        left.truncate(MAX_FIR_LEN);
+        right.truncate(MAX_FIR_LEN);
+
+        // Leave enough headroom for the loudest frequency the inverse filter boosts
+        let peak = fir_peak_gain(&left).max(fir_peak_gain(&right));
+        let preamp_db = -20.0 * peak.max(1e-6).log10();
+
+        Ok(EqProfile::Fir {
+            left,
+            right,
+            sample_rate: spec.sample_rate,
+            preamp_db: preamp_db.min(0.0),
+        })
+    }
+
+    /// Parse the AutoEQ `ParametricEQ.txt` format. Unknown lines and filters that are OFF are skipped.
+    pub fn parse_parametric(text: &str) -> Result<Self, String> {
+        let mut preamp_db = 0.0;
+        let mut bands = Vec::new();
+
+        for line in text.lines() {
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            match tokens.first() {
+                Some(&"Preamp:") => {
+                    preamp_db = parse_number(tokens.get(1), "Preamp")?;
+                }
+                Some(&"Filter") | Some(&"Filter:") => {
+                    if let Some(band) = parse_band(&tokens)? {
+                        bands.push(band);
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        if bands.is_empty() {
+            return Err(String::from("no filters found in the profile"));
+        }
+
+        Ok(EqProfile::Parametric { bands, preamp_db })
+    }
+
+    pub fn preamp_db(&self) -> f32 {
+        match self {
+            EqProfile::Fir { preamp_db, .. } | EqProfile::Parametric { preamp_db, .. } => *preamp_db,
+        }
+    }
+}
+
+fn parse_number(token: Option<&&str>, what: &str) -> Result<f32, String> {
+    token
+        .and_then(|t| t.parse().ok())
+        .ok_or_else(|| format!("could not parse the {what} value"))
+}
+
+fn parse_band(tokens: &[&str]) -> Result<Option<Band>, String> {
+    // Filter 1: ON PK Fc 105 Hz Gain -2.3 dB Q 0.70
+    if !tokens.contains(&"ON") {
+        return Ok(None);
+    }
+
+    let value = |key: &str| {
+        tokens
+            .iter()
+            .position(|t| *t == key)
+            .map(|idx| parse_number(tokens.get(idx + 1), key))
+    };
+
+    let band_type = match tokens.iter().find_map(|t| match *t {
+        "PK" | "PEQ" => Some(BandType::Peak),
+        "LS" | "LSC" => Some(BandType::LowShelf),
+        "HS" | "HSC" => Some(BandType::HighShelf),
+        "LP" | "LPQ" => Some(BandType::LowPass),
+        "HP" | "HPQ" => Some(BandType::HighPass),
+        _ => None,
+    }) {
+        Some(band_type) => band_type,
+        None => return Err(format!("unsupported filter type in '{}'", tokens.join(" "))),
+    };
+
+    let freq = value("Fc").ok_or("filter without a frequency")??;
+    let gain_db = value("Gain").transpose()?.unwrap_or(0.0);
+    // AutoEQ leaves out Q for some shelves, those use 0.71
+    let q = value("Q").transpose()?.unwrap_or(std::f32::consts::FRAC_1_SQRT_2);
+
+    Ok(Some(Band {
+        band_type,
+        freq,
+        gain_db,
+        q,
+    }))
+}
+
+/// Largest magnitude of the filter's frequency response.
+fn fir_peak_gain(ir: &[f32]) -> f32 {
+    let fft_len = ir.len().next_power_of_two().max(1024) * 2;
+    let mut planner = RealFftPlanner::<f32>::new();
+    let r2c = planner.plan_fft_forward(fft_len);
+
+    let mut input = r2c.make_input_vec();
+    input[..ir.len()].copy_from_slice(ir);
+    let mut spectrum = r2c.make_output_vec();
+    r2c.process(&mut input, &mut spectrum).unwrap();
+
+    spectrum.iter().map(|bin| bin.norm()).fold(0.0, f32::max)
+}
+
+/// RBJ cookbook biquad in transposed direct form II.
+#[derive(Debug, Clone, Copy, Default)]
+struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+    s1: f64,
+    s2: f64,
+}
+
+impl Biquad {
+    fn new(band: &Band, sample_rate: f32) -> Self {
+        let w0 = 2.0 * PI * (band.freq as f64 / sample_rate as f64).min(0.49);
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * band.q.max(0.01) as f64);
+        let a = 10f64.powf(band.gain_db as f64 / 40.0);
+
+        let (b0, b1, b2, a0, a1, a2) = match band.band_type {
+            BandType::Peak => (
+                1.0 + alpha * a,
+                -2.0 * cos,
+                1.0 - alpha * a,
+                1.0 + alpha / a,
+                -2.0 * cos,
+                1.0 - alpha / a,
+            ),
+            BandType::LowShelf => {
+                let sq = 2.0 * a.sqrt() * alpha;
+                (
+                    a * ((a + 1.0) - (a - 1.0) * cos + sq),
+                    2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                    a * ((a + 1.0) - (a - 1.0) * cos - sq),
+                    (a + 1.0) + (a - 1.0) * cos + sq,
+                    -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                    (a + 1.0) + (a - 1.0) * cos - sq,
+                )
+            }
+            BandType::HighShelf => {
+                let sq = 2.0 * a.sqrt() * alpha;
+                (
+                    a * ((a + 1.0) + (a - 1.0) * cos + sq),
+                    -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                    a * ((a + 1.0) + (a - 1.0) * cos - sq),
+                    (a + 1.0) - (a - 1.0) * cos + sq,
+                    2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                    (a + 1.0) - (a - 1.0) * cos - sq,
+                )
+            }
+            BandType::LowPass => (
+                (1.0 - cos) / 2.0,
+                1.0 - cos,
+                (1.0 - cos) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ),
+            BandType::HighPass => (
+                (1.0 + cos) / 2.0,
+                -(1.0 + cos),
+                (1.0 + cos) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ),
+        };
+
+        Biquad {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+            s1: 0.0,
+            s2: 0.0,
+        }
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let x = input as f64;
+        let y = self.b0 * x + self.s1;
+        self.s1 = self.b1 * x - self.a1 * y + self.s2;
+        self.s2 = self.b2 * x - self.a2 * y;
+
+        y as f32
+    }
+
+    fn reset(&mut self) {
+        self.s1 = 0.0;
+        self.s2 = 0.0;
+    }
+}
+
+/// Time domain FIR with the history stored twice so every output sample is one contiguous dot
+/// product.
+#[derive(Debug, Clone)]
+struct Fir {
+    /// Taps in reverse order
+    taps: Vec<f32>,
+    history: Vec<f32>,
+    pos: usize,
+}
+
+impl Fir {
+    fn new(ir: &[f32]) -> Self {
+        Fir {
+            taps: ir.iter().rev().copied().collect(),
+            history: vec![0.0; ir.len() * 2],
+            pos: 0,
+        }
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let len = self.taps.len();
+        self.history[self.pos] = input;
+        self.history[self.pos + len] = input;
+        self.pos = (self.pos + 1) % len;
+
+        // history[pos..pos + len] now runs from the oldest to the newest sample
+        self.history[self.pos..self.pos + len]
+            .iter()
+            .zip(&self.taps)
+            .map(|(x, h)| x * h)
+            .sum()
+    }
+
+    fn reset(&mut self) {
+        self.history.fill(0.0);
+        self.pos = 0;
+    }
+}
+
+fn peak_index(ir: &[f32]) -> usize {
+    ir.iter()
+        .enumerate()
+        .fold((0, 0.0), |(peak, max), (n, s)| if s.abs() > max { (n, s.abs()) } else { (peak, max) })
+        .0
+}
+
+#[derive(Debug, Clone)]
+enum Stage {
+    Fir { left: Fir, right: Fir },
+    Parametric { left: Vec<Biquad>, right: Vec<Biquad> },
+}
+
+/// The headphone compensation stage, everything is allocated up front in [`HeadphoneEq::new`].
+#[derive(Debug, Clone)]
+pub struct HeadphoneEq {
+    stage: Stage,
+    preamp: f32,
+    /// Where the main peak of an FIR profile is, linear phase filters put it in the middle
+    latency: u32,
+}
+
+impl HeadphoneEq {
+    pub fn new(profile: &EqProfile, sample_rate: f32) -> Result<Self, Error> {
+        let mut latency = 0;
+        let stage = match profile {
+            EqProfile::Fir {
+                left,
+                right,
+                sample_rate: fir_rate,
+                ..
+            } => {
+                if *fir_rate as f32 != sample_rate {
+                    return Err(Error::EqSampleRate {
+                        profile: *fir_rate,
+                        plugin: sample_rate,
+                    });
+                }
+
+                latency = peak_index(left).min(peak_index(right)) as u32;
+                Stage::Fir {
+                    left: Fir::new(left),
+                    right: Fir::new(right),
+                }
+            }
+            EqProfile::Parametric { bands, .. } => {
+                let biquads: Vec<Biquad> =
+                    bands.iter().map(|band| Biquad::new(band, sample_rate)).collect();
+                Stage::Parametric {
+                    left: biquads.clone(),
+                    right: biquads,
+                }
+            }
+        };
+
+        Ok(HeadphoneEq {
+            stage,
+            preamp: 10f32.powf(profile.preamp_db() / 20.0),
+            latency,
+        })
+    }
+
+    /// The delay the EQ adds in samples, the position of the FIR's main peak. Parametric profiles
+    /// are minimum phase and add none.
+    pub fn latency_samples(&self) -> u32 {
+        self.latency
+    }
+
+    pub fn process(&mut self, left: &mut [f32], right: &mut [f32]) {
+        match &mut self.stage {
+            Stage::Fir { left: fir_l, right: fir_r } => {
+                for sample in left.iter_mut() {
+                    *sample = fir_l.process(*sample * self.preamp);
+                }
+                for sample in right.iter_mut() {
+                    *sample = fir_r.process(*sample * self.preamp);
+                }
+            }
+            Stage::Parametric { left: bq_l, right: bq_r } => {
+                for (channel, biquads) in [(left, bq_l), (right, bq_r)] {
+                    for sample in channel.iter_mut() {
+                        *sample = biquads
+                            .iter_mut()
+                            .fold(*sample * self.preamp, |s, biquad| biquad.process(s));
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn reset(&mut self) {
+        match &mut self.stage {
+            Stage::Fir { left, right } => {
+                left.reset();
+                right.reset();
+            }
+            Stage::Parametric { left, right } => {
+                left.iter_mut().chain(right.iter_mut()).for_each(Biquad::reset);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    const PROFILE: &str = "Preamp: -6.2 dB
+Filter 1: ON LSC Fc 105 Hz Gain 5.5 dB Q 0.70
+Filter 2: ON PK Fc 2263 Hz Gain -3.4 dB Q 1.27
+Filter 3: OFF PK Fc 5000 Hz Gain 2.0 dB Q 2.00
+Filter 4: ON HSC Fc 10000 Hz Gain -2.1 dB Q 0.70
+";
+
+    #[test]
+    fn test_parse_autoeq() {
+        let profile = EqProfile::parse_parametric(PROFILE).unwrap();
+        assert_approx_eq!(profile.preamp_db(), -6.2);
+
+        let EqProfile::Parametric { bands, .. } = profile else {
+            panic!("expected a parametric profile");
+        };
+        assert_eq!(bands.len(), 3);
+        assert_eq!(bands[0].band_type, BandType::LowShelf);
+        assert_approx_eq!(bands[1].freq, 2263.0);
+        assert_approx_eq!(bands[1].gain_db, -3.4);
+        assert_approx_eq!(bands[2].q, 0.7);
+    }
+
+    #[test]
+    fn test_fir_convolution() {
+        let profile = EqProfile::Fir {
+            left: vec![1.0, 0.5, 0.25],
+            right: vec![0.0, 1.0],
+            sample_rate: 48000,
+            preamp_db: 0.0,
+        };
+        let mut eq = HeadphoneEq::new(&profile, 48000.0).unwrap();
+        // the earlier of the two peaks
+        assert_eq!(eq.latency_samples(), 0);
+
+        let mut left = [1.0, 0.0, 0.0, 0.0];
+        let mut right = [1.0, 0.0, 0.0, 0.0];
+        eq.process(&mut left, &mut right);
+
+        assert_eq!(left, [1.0, 0.5, 0.25, 0.0]);
+        assert_eq!(right, [0.0, 1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_peak_gain_at_centre() {
+        let band = Band {
+            band_type: BandType::Peak,
+            freq: 1000.0,
+            gain_db: 6.0,
+            q: 1.0,
+        };
+        let mut biquad = Biquad::new(&band, 48000.0);
+
+        // steady state amplitude of a 1 kHz sine should be 6 dB up
+        let mut peak: f32 = 0.0;
+        for n in 0..48000 {
+            let x = (2.0 * std::f32::consts::PI * 1000.0 * n as f32 / 48000.0).sin();
+            let y = biquad.process(x);
+            if n > 24000 {
+                peak = peak.max(y.abs());
+            }
+        }
+        assert_approx_eq!(20.0 * peak.log10(), 6.0, 0.05);
+    }
+}
@@ -0,0 +1,592 @@
+// Where the HRIRs come from and how they are convolved with the source. `SpatializerEfx` only talks
+// to an `HrtfBackend`, so the SOFA datasets read by sofar and the HRIR spheres of the `hrtf` crate
+// can be compared on the same signal chain.
+
+use hrtf::{HrirSphere, HrtfContext, HrtfProcessor, Vec3};
+use nih_plug::nih_debug_assert_failure;
+use sofar::reader::{Filter, Sofar};
+use sofar::render::Renderer;
+
+use crate::head_model::{self, HeadModel};
+use crate::ola::OlaConvolver;
+use crate::sofa_info;
+#[cfg(test)]
+use crate::sofa_writer::HrirSet;
+use crate::spatializer_efx::{spherical_to_cartesian, ConvolutionEngine, Error, PARTITION_LEN};
+
+use std::path::Path;
+
+/// Latency of `sofar::render::Renderer`. Its partitioned convolution mixes the partially filled
+/// current partition in directly, so the first output sample already depends on the first input
+/// sample.
+const RENDER_LATENCY: u32 = 0;
+/// An HRIR's onset is its first sample within this factor of the pair's peak.
+pub(crate) const ONSET_THRESHOLD: f32 = 0.1;
+
+/// The `hrtf` processor convolves blocks of this many samples and interpolates between the old and
+/// the new HRIR over this many blocks after a direction change.
+const SPHERE_BLOCK_LEN: usize = 32;
+const SPHERE_INTERPOLATION_STEPS: usize = 2;
+const SPHERE_FRAME_LEN: usize = SPHERE_BLOCK_LEN * SPHERE_INTERPOLATION_STEPS;
+
+/// An HRIR source plus the convolution that applies it to a mono signal. Positions are SOFA
+/// cartesian coordinates (+x front, +y left, +z up) in metres.
+pub trait HrtfBackend: Send {
+    /// Length of the HRIRs in samples
+    fn filter_len(&self) -> usize;
+
+    /// Render the source from this position from the next block on. Real-time safe.
+    fn set_position(&mut self, x: f32, y: f32, z: f32);
+
+    /// Personalise the HRIRs to the listener's head, see [`HeadModel::apply`]. Takes effect with
+    /// the next `set_position`, backends that can't rescale their HRIRs ignore it.
+    fn set_head_size(&mut self, _circumference: f32, _pinna_shift: bool) {}
+
+    /// Backends with a single convolution engine ignore this.
+    fn set_engine(&mut self, _engine: ConvolutionEngine) {}
+
+    fn process_block(&mut self, input: &[f32], left: &mut [f32], right: &mut [f32]);
+
+    /// Clear the convolution history without reallocating anything.
+    fn reset(&mut self);
+
+    /// How many samples after the input the direct sound reaches the nearer ear for the direction
+    /// it arrives earliest from.
+    fn latency_samples(&self) -> u32;
+
+    /// The HRIR pair in use, for backends that expose it.
+    fn filter(&self) -> Option<&Filter> {
+        None
+    }
+}
+
+/// Open `path` with a backend that is safe to run on the audio thread, which rules out HRIR
+/// spheres. Everything is opened as a SOFA dataset with [`SofarBackend`]. Spheres are refused
+/// rather than rendered with allocations, so the plugin, the standalone and `ase-player` only
+/// play SOFA datasets and comparing the two backends is done offline with `ase-render`.
+pub fn open<P: AsRef<Path>>(path: P, sample_rate: f32) -> Result<Box<dyn HrtfBackend>, Error> {
+    let path = path.as_ref();
+    if is_sphere(path) {
+        return Err(Error::SphereNotRealtime(path.display().to_string()));
+    }
+
+    let sofa = sofa_info::open_sofa(path, sample_rate)?;
+    Ok(Box::new(SofarBackend::new(sofa, sample_rate)?))
+}
+
+/// Like [`open`], but `.bin` HRIR spheres are opened with [`SphereBackend`]. For the offline tools
+/// only.
+pub fn open_offline<P: AsRef<Path>>(
+    path: P,
+    sample_rate: f32,
+) -> Result<Box<dyn HrtfBackend>, Error> {
+    let path = path.as_ref();
+    if is_sphere(path) {
+        Ok(Box::new(SphereBackend::open(path, sample_rate)?))
+    } else {
+        open(path, sample_rate)
+    }
+}
+
+fn is_sphere(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("bin"))
+}
+
+/// SOFA datasets looked up by libmysofa, personalised by the head model and convolved by either
+/// `sofar::render::Renderer` or [`OlaConvolver`].
+pub struct SofarBackend {
+    sofa: Sofar,
+    engine: ConvolutionEngine,
+    render: Renderer,
+    ola: OlaConvolver,
+    filter: Filter,
+    head: HeadModel,
+    head_size: f32,
+    pinna_shift: bool,
+    /// The earliest onset of any HRIR in the dataset, see `latency_samples`
+    pre_delay: usize,
+    // Scratch buffers for flushing the renderer with silence
+    silence: Vec<f32>,
+    flush_left: Vec<f32>,
+    flush_right: Vec<f32>,
+}
+
+impl SofarBackend {
+    pub fn new(sofa: Sofar, sample_rate: f32) -> Result<Self, Error> {
+        let filt_len = sofa.filter_len();
+
+        // sofa Renderer, kept across calls so the convolution tail carries over between blocks
+        let render = Renderer::builder(filt_len)
+            .with_sample_rate(sample_rate)
+            .with_partition_len(PARTITION_LEN)
+            .build()
+            .map_err(|err| Error::Sofar(err.to_string()))?;
+
+        let pre_delay = dataset_onset(&sofa);
+        let mut head = HeadModel::new(filt_len, sample_rate);
+        head.set_onset(pre_delay);
+
+        Ok(SofarBackend {
+            pre_delay,
+            engine: ConvolutionEngine::Sofar,
+            render,
+            ola: OlaConvolver::new(filt_len, PARTITION_LEN),
+            filter: Filter::new(filt_len),
+            head,
+            head_size: head_model::REFERENCE_CIRCUMFERENCE,
+            pinna_shift: false,
+            silence: vec![0.0; PARTITION_LEN],
+            flush_left: vec![0.0; PARTITION_LEN],
+            flush_right: vec![0.0; PARTITION_LEN],
+            sofa,
+        })
+    }
+
+    /// The renderer has no way to clear its history, so flush it with silence instead.
+    fn flush_renderer(&mut self) {
+        for _ in 0..self.sofa.filter_len().div_ceil(PARTITION_LEN) + 1 {
+            let flushed = self.render.process_block(
+                &self.silence,
+                &mut self.flush_left,
+                &mut self.flush_right,
+            );
+            if let Err(err) = flushed {
+                nih_debug_assert_failure!("Failed to flush the renderer: {err}");
+                return;
+            }
+        }
+    }
+
+    /// A filter the renderer refuses leaves it on the previous one.
+    fn apply_filter(&mut self) {
+        match self.engine {
+            ConvolutionEngine::Sofar => {
+                if let Err(err) = self.render.set_filter(&self.filter) {
+                    nih_debug_assert_failure!("Failed to set the renderer's filter: {err}");
+                }
+            }
+            ConvolutionEngine::OverlapAdd => self.ola.set_filter(&self.filter),
+        }
+    }
+}
+
+impl HrtfBackend for SofarBackend {
+    fn filter_len(&self) -> usize {
+        self.sofa.filter_len()
+    }
+
+    fn set_position(&mut self, x: f32, y: f32, z: f32) {
+        // get filter at position and personalise it to the listener's head
+        self.sofa.filter(x, y, z, &mut self.filter);
+        self.head
+            .apply(x, y, z, self.head_size, self.pinna_shift, &mut self.filter);
+        self.apply_filter();
+    }
+
+    fn set_head_size(&mut self, circumference: f32, pinna_shift: bool) {
+        self.head_size = circumference;
+        self.pinna_shift = pinna_shift;
+    }
+
+    /// The newly selected engine starts from silence, only the engine in use is kept up to date.
+    fn set_engine(&mut self, engine: ConvolutionEngine) {
+        if engine == self.engine {
+            return;
+        }
+
+        self.engine = engine;
+        match engine {
+            ConvolutionEngine::Sofar => self.flush_renderer(),
+            ConvolutionEngine::OverlapAdd => self.ola.reset(),
+        }
+        self.apply_filter();
+    }
+
+    /// Outputs silence for a block the renderer fails on rather than panicking on the audio
+    /// thread.
+    fn process_block(&mut self, input: &[f32], left: &mut [f32], right: &mut [f32]) {
+        match self.engine {
+            ConvolutionEngine::Sofar => {
+                if let Err(err) = self.render.process_block(input, left, right) {
+                    nih_debug_assert_failure!("Failed to render a block: {err}");
+                    left.fill(0.0);
+                    right.fill(0.0);
+                }
+            }
+            ConvolutionEngine::OverlapAdd => self.ola.process_block(input, left, right),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.flush_renderer();
+        self.ola.reset();
+    }
+
+    /// The engine's block delay plus the HRIRs' common pre-delay. The head model keeps the onset
+    /// in place, so the head size never changes this.
+    fn latency_samples(&self) -> u32 {
+        let engine = match self.engine {
+            ConvolutionEngine::Sofar => RENDER_LATENCY,
+            ConvolutionEngine::OverlapAdd => self.ola.latency_samples(),
+        };
+
+        engine + self.pre_delay as u32
+    }
+
+    fn filter(&self) -> Option<&Filter> {
+        Some(&self.filter)
+    }
+}
+
+/// The earliest HRIR onset over directions every 15 degrees around the listener, in samples.
+/// Datasets usually start every IR with the same stretch of silence before the sound reaches the
+/// nearer ear.
+fn dataset_onset(sofa: &Sofar) -> usize {
+    let mut filter = Filter::new(sofa.filter_len());
+    let mut onset = sofa.filter_len();
+
+    for elevation in (-45..=90).step_by(15) {
+        for azimuth in (0..360).step_by(15) {
+            let (x, y, z) = spherical_to_cartesian(azimuth as f32, elevation as f32, 1.0);
+            sofa.filter(x, y, z, &mut filter);
+
+            let peak = filter
+                .left
+                .iter()
+                .chain(filter.right.iter())
+                .fold(0.0f32, |peak, s| peak.max(s.abs()));
+            if peak <= 0.0 {
+                continue;
+            }
+            let first = |ir: &[f32]| ir.iter().position(|s| s.abs() >= peak * ONSET_THRESHOLD);
+            for ear_onset in [first(&filter.left), first(&filter.right)].into_iter().flatten() {
+                onset = onset.min(ear_onset);
+            }
+        }
+    }
+
+    // a dataset of silence has no onset to compensate for
+    if onset == sofa.filter_len() {
+        0
+    } else {
+        onset
+    }
+}
+
+/// HRIRs held in memory, e.g. the synthetic spherical head, rendered from the nearest measurement
+/// with [`OlaConvolver`]. Pure Rust without libmysofa's interpolation and loudness normalisation,
+/// so the output is the same with every version of the C library. Only built for the tests.
+#[cfg(test)]
+pub struct MemoryBackend {
+    /// Unit vectors towards the measurements in SOFA coordinates
+    directions: Vec<(f32, f32, f32)>,
+    set: HrirSet,
+    measurement: Option<usize>,
+    ola: OlaConvolver,
+    filter: Filter,
+    /// The earliest onset of any HRIR in the set
+    pre_delay: usize,
+}
+
+#[cfg(test)]
+impl MemoryBackend {
+    pub fn new(set: HrirSet) -> Self {
+        let directions = set
+            .source_positions
+            .iter()
+            .map(|&[azimuth, elevation, _]| {
+                spherical_to_cartesian(azimuth as f32, elevation as f32, 1.0)
+            })
+            .collect();
+
+        let mut pre_delay = set.filter_len;
+        for measurement in 0..set.measurements() {
+            let (left, right) = (set.ir(measurement, 0), set.ir(measurement, 1));
+            let peak = left.iter().chain(right).fold(0.0f64, |peak, s| peak.max(s.abs()));
+            let threshold = peak * ONSET_THRESHOLD as f64;
+            for ir in [left, right] {
+                if let Some(onset) = ir.iter().position(|s| peak > 0.0 && s.abs() >= threshold) {
+                    pre_delay = pre_delay.min(onset);
+                }
+            }
+        }
+
+        MemoryBackend {
+            directions,
+            measurement: None,
+            ola: OlaConvolver::new(set.filter_len, PARTITION_LEN),
+            filter: Filter::new(set.filter_len),
+            pre_delay: if pre_delay == set.filter_len { 0 } else { pre_delay },
+            set,
+        }
+    }
+}
+
+#[cfg(test)]
+impl HrtfBackend for MemoryBackend {
+    fn filter_len(&self) -> usize {
+        self.set.filter_len
+    }
+
+    fn set_position(&mut self, x: f32, y: f32, z: f32) {
+        let nearest = self
+            .directions
+            .iter()
+            .map(|&(dx, dy, dz)| dx * x + dy * y + dz * z)
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(measurement, _)| measurement);
+        let Some(measurement) = nearest.filter(|&nearest| Some(nearest) != self.measurement) else {
+            return;
+        };
+
+        self.measurement = Some(measurement);
+        for (ear, ir) in [&mut self.filter.left, &mut self.filter.right].into_iter().enumerate() {
+            for (tap, &s) in ir.iter_mut().zip(self.set.ir(measurement, ear)) {
+                *tap = s as f32;
+            }
+        }
+        self.ola.set_filter(&self.filter);
+    }
+
+    fn process_block(&mut self, input: &[f32], left: &mut [f32], right: &mut [f32]) {
+        self.ola.process_block(input, left, right);
+    }
+
+    fn reset(&mut self) {
+        self.ola.reset();
+    }
+
+    fn latency_samples(&self) -> u32 {
+        self.ola.latency_samples() + self.pre_delay as u32
+    }
+
+    fn filter(&self) -> Option<&Filter> {
+        Some(&self.filter)
+    }
+}
+
+/// HRIR spheres in the `hrtf` crate's `.bin` format (e.g. the IRCAM spheres converted by
+/// hrir_sphere_builder). The crate interpolates between the three measurements around the
+/// direction and crossfades to a new direction over `SPHERE_INTERPOLATION_STEPS` blocks.
+///
+/// The crate's processor allocates while rendering (its FFTs allocate their scratch space), so this
+/// backend is only for comparing quality and CPU cost offline and [`open`] refuses spheres.
+pub struct SphereBackend {
+    processor: HrtfProcessor,
+    filter_len: usize,
+    /// Direction of the current and the previous frame, in the crate's coordinates
+    vector: Vec3,
+    prev_vector: Vec3,
+    /// The convolution tails the processor carries over between frames
+    prev_left: Vec<f32>,
+    prev_right: Vec<f32>,
+    /// The frame being filled and the last rendered frame being played back
+    input: Vec<f32>,
+    output: Vec<(f32, f32)>,
+    pos: usize,
+    /// The earliest HRIR onset of the sphere, see `latency_samples`
+    pre_delay: usize,
+}
+
+impl SphereBackend {
+    pub fn open<P: AsRef<Path>>(path: P, sample_rate: f32) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let sphere = HrirSphere::from_file(path, sample_rate as u32).map_err(|err| Error::Open {
+            path: path.display().to_string(),
+            reason: format!("{err:?}"),
+        })?;
+        let filter_len = sphere.len();
+        let front = sphere_vector(1.0, 0.0, 0.0);
+
+        let mut backend = SphereBackend {
+            processor: HrtfProcessor::new(sphere, SPHERE_INTERPOLATION_STEPS, SPHERE_BLOCK_LEN),
+            filter_len,
+            vector: front,
+            prev_vector: front,
+            prev_left: vec![0.0; filter_len],
+            prev_right: vec![0.0; filter_len],
+            input: vec![0.0; SPHERE_FRAME_LEN],
+            output: vec![(0.0, 0.0); SPHERE_FRAME_LEN],
+            pos: 0,
+            pre_delay: 0,
+        };
+        backend.pre_delay = backend.sphere_onset();
+        backend.vector = front;
+        backend.reset();
+
+        Ok(backend)
+    }
+
+    fn render_frame(&mut self) {
+        // the processor adds to the output
+        self.output.fill((0.0, 0.0));
+        self.processor.process_samples(HrtfContext {
+            source: &self.input,
+            output: &mut self.output,
+            new_sample_vector: self.vector,
+            prev_sample_vector: self.prev_vector,
+            prev_left_samples: &mut self.prev_left,
+            prev_right_samples: &mut self.prev_right,
+            new_distance_gain: 1.0,
+            prev_distance_gain: 1.0,
+        });
+        self.prev_vector = self.vector;
+    }
+
+    /// The earliest HRIR onset over directions every 15 degrees around the listener, in samples,
+    /// like `dataset_onset`. The sphere doesn't hand out its HRIRs, so this renders an impulse
+    /// from every direction instead.
+    fn sphere_onset(&mut self) -> usize {
+        let frames = self.filter_len.div_ceil(SPHERE_FRAME_LEN) + 1;
+        let mut response = Vec::with_capacity(frames * SPHERE_FRAME_LEN);
+        let mut onset = usize::MAX;
+
+        for elevation in (-45..=90).step_by(15) {
+            for azimuth in (0..360).step_by(15) {
+                let (x, y, z) = spherical_to_cartesian(azimuth as f32, elevation as f32, 1.0);
+                self.set_position(x, y, z);
+                self.reset();
+
+                response.clear();
+                self.input[0] = 1.0;
+                for _ in 0..frames {
+                    self.render_frame();
+                    response.extend_from_slice(&self.output);
+                    self.input.fill(0.0);
+                }
+
+                let peak = response
+                    .iter()
+                    .fold(0.0f32, |peak, (l, r)| peak.max(l.abs()).max(r.abs()));
+                if peak <= 0.0 {
+                    continue;
+                }
+                let threshold = peak * ONSET_THRESHOLD;
+                if let Some(first) = response
+                    .iter()
+                    .position(|(l, r)| l.abs() >= threshold || r.abs() >= threshold)
+                {
+                    onset = onset.min(first);
+                }
+            }
+        }
+
+        // a sphere of silence has no onset to compensate for
+        if onset == usize::MAX {
+            0
+        } else {
+            onset
+        }
+    }
+}
+
+impl HrtfBackend for SphereBackend {
+    fn filter_len(&self) -> usize {
+        self.filter_len
+    }
+
+    fn set_position(&mut self, x: f32, y: f32, z: f32) {
+        self.vector = sphere_vector(x, y, z);
+    }
+
+    fn process_block(&mut self, input: &[f32], left: &mut [f32], right: &mut [f32]) {
+        for ((&sample, left), right) in input.iter().zip(left).zip(right) {
+            self.input[self.pos] = sample;
+            (*left, *right) = self.output[self.pos];
+
+            self.pos += 1;
+            if self.pos == SPHERE_FRAME_LEN {
+                self.render_frame();
+                self.pos = 0;
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.prev_left.fill(0.0);
+        self.prev_right.fill(0.0);
+        self.input.fill(0.0);
+        self.output.fill((0.0, 0.0));
+        self.pos = 0;
+        self.prev_vector = self.vector;
+    }
+
+    /// Input is collected for a whole frame before it is rendered, plus the HRIRs' common
+    /// pre-delay.
+    fn latency_samples(&self) -> u32 {
+        (SPHERE_FRAME_LEN + self.pre_delay) as u32
+    }
+}
+
+/// SOFA coordinates to the `hrtf` crate's listener space, +x right, +y up and +z front.
+fn sphere_vector(x: f32, y: f32, z: f32) -> Vec3 {
+    Vec3::new(-y, z, x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sphere_vector() {
+        // front, left and up in the listener space of the hrtf crate
+        for ((x, y, z), expected) in [
+            ((1.0, 0.0, 0.0), (0.0, 0.0, 1.0)),
+            ((0.0, 1.0, 0.0), (-1.0, 0.0, 0.0)),
+            ((0.0, 0.0, 1.0), (0.0, 1.0, 0.0)),
+        ] {
+            let vector = sphere_vector(x, y, z);
+            assert_eq!((vector.x, vector.y, vector.z), expected);
+        }
+    }
+
+    #[test]
+    fn test_open_missing_sphere() {
+        let path = std::env::temp_dir().join("ase-project-missing-sphere.bin");
+        assert!(matches!(open_offline(&path, 48000.0), Err(Error::Open { .. })));
+    }
+
+    #[test]
+    fn test_sphere_impulse_latency() {
+        // an octahedron whose HRIRs are single impulses 8 samples in, apart from the left and right
+        // vertices where the near ear is 5 and the far ear 11 samples in, see
+        // `fixtures::octahedron_sphere`
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/octahedron_sphere.bin");
+        assert!(matches!(open(&path, 48000.0), Err(Error::SphereNotRealtime(_))));
+
+        let mut sphere = open_offline(&path, 48000.0).unwrap();
+        assert_eq!(sphere.filter_len(), 32);
+        assert_eq!(sphere.latency_samples(), SPHERE_FRAME_LEN as u32 + 5);
+
+        // first sample of each ear within the onset threshold of the louder ear's peak
+        let onsets = |sphere: &mut Box<dyn HrtfBackend>, (x, y, z)| {
+            let mut input = [0.0; 256];
+            input[0] = 1.0;
+            let mut left = [0.0; 256];
+            let mut right = [0.0; 256];
+            sphere.reset();
+            sphere.set_position(x, y, z);
+            for ((input, left), right) in input
+                .chunks(100)
+                .zip(left.chunks_mut(100))
+                .zip(right.chunks_mut(100))
+            {
+                sphere.process_block(input, left, right);
+            }
+
+            let peak = left.iter().chain(&right).fold(0.0f32, |peak, s| peak.max(s.abs()));
+            let first = |ch: &[f32]| ch.iter().position(|s| s.abs() >= peak * ONSET_THRESHOLD);
+            (first(&left).unwrap() as u32, first(&right).unwrap() as u32)
+        };
+
+        // just off the side vertices, so the lookup doesn't land on the edges between faces
+        let (left, right) = onsets(&mut sphere, (0.1, 1.0, 0.1));
+        assert_eq!(left, sphere.latency_samples());
+        assert!(right > left);
+
+        let (left, right) = onsets(&mut sphere, (0.1, -1.0, 0.1));
+        assert_eq!(right, sphere.latency_samples());
+        assert!(left > right);
+    }
+}
@@ -0,0 +1,256 @@
+// The HRTF view of the editor: the HRIRs the renderer is currently using, their magnitude
+// responses and where the loaded dataset has measurements
+
+use nih_plug_egui::egui::{self, Align2, Color32, FontId, Pos2, Rect, Sense, Shape, Stroke, Vec2};
+use realfft::RealFftPlanner;
+use sofar::reader::Filter;
+
+use crate::sofa_info::SofaInfo;
+
+use std::path::Path;
+
+const LEFT: Color32 = Color32::from_rgb(80, 160, 240);
+const RIGHT: Color32 = Color32::from_rgb(240, 90, 80);
+const GRID: Color32 = Color32::from_rgb(60, 64, 72);
+const BACKGROUND: Color32 = Color32::from_rgb(24, 26, 31);
+const POINT: Color32 = Color32::from_rgb(130, 130, 140);
+const SOURCE: Color32 = Color32::from_rgb(240, 150, 40);
+
+/// The magnitude plot shows this many dB below its top
+const DB_RANGE: f32 = 60.0;
+
+/// A copy of the filter `process` last gave to the renderer, shared with the editor. The audio
+/// thread only writes it with `try_lock` into buffers sized in `initialize`, so it never blocks or
+/// allocates.
+#[derive(Debug, Clone, Default)]
+pub struct HrtfView {
+    pub sample_rate: f32,
+    pub left: Vec<f32>,
+    pub right: Vec<f32>,
+    /// (azimuth, elevation, distance) the filter was looked up for
+    pub position: (f32, f32, f32),
+    /// (azimuth, elevation) of every measurement in the dataset, in degrees
+    pub coverage: Vec<(f32, f32)>,
+    /// Incremented on every update, so the editor knows when to recompute the spectra
+    pub generation: u64,
+}
+
+impl HrtfView {
+    /// Size the buffers for a newly loaded dataset, not real-time safe.
+    pub fn load<P: AsRef<Path>>(&mut self, path: P, sample_rate: f32, filter_len: usize) {
+        self.sample_rate = sample_rate;
+        self.left = vec![0.0; filter_len];
+        self.right = vec![0.0; filter_len];
+        self.coverage = SofaInfo::open(path)
+            .map(|info| {
+                info.spherical_positions()
+                    .iter()
+                    .map(|&[az, el, _]| (az as f32, el as f32))
+                    .collect()
+            })
+            .unwrap_or_default();
+        self.generation += 1;
+    }
+
+    /// Copy the filter, real-time safe.
+    pub fn update(&mut self, filter: &Filter, position: (f32, f32, f32)) {
+        if filter.left.len() != self.left.len() {
+            return;
+        }
+
+        self.left.copy_from_slice(&filter.left);
+        self.right.copy_from_slice(&filter.right);
+        self.position = position;
+        self.generation += 1;
+    }
+}
+
+/// Magnitude response in dB of `ir` from DC to Nyquist, zero padded to at least 512 taps so the
+/// curve is smooth.
+pub fn magnitude_db(ir: &[f32]) -> Vec<f32> {
+    let fft_len = ir.len().max(512).next_power_of_two();
+    let mut planner = RealFftPlanner::<f32>::new();
+    let r2c = planner.plan_fft_forward(fft_len);
+
+    let mut input = r2c.make_input_vec();
+    input[..ir.len()].copy_from_slice(ir);
+    let mut spectrum = r2c.make_output_vec();
+    r2c.process(&mut input, &mut spectrum).unwrap();
+
+    spectrum
+        .iter()
+        .map(|bin| 20.0 * bin.norm().max(1e-6).log10())
+        .collect()
+}
+
+/// The spectra of the last shown generation, computed on the GUI thread
+#[derive(Default)]
+pub struct Spectra {
+    generation: u64,
+    left: Vec<f32>,
+    right: Vec<f32>,
+}
+
+pub fn show(ui: &mut egui::Ui, view: &HrtfView, spectra: &mut Spectra) {
+    if view.left.is_empty() {
+        ui.label("No dataset loaded");
+        return;
+    }
+
+    if spectra.generation != view.generation {
+        spectra.left = magnitude_db(&view.left);
+        spectra.right = magnitude_db(&view.right);
+        spectra.generation = view.generation;
+    }
+
+    let (azimuth, elevation, distance) = view.position;
+    ui.label(format!(
+        "HRIR at azimuth {azimuth:.1}°, elevation {elevation:.1}°, distance {distance:.2} m   \
+         (left blue, right red)"
+    ));
+
+    let width = ui.available_width();
+    impulse_responses(ui, view, width);
+    magnitudes(ui, spectra, view.sample_rate, width);
+
+    ui.label(format!("Dataset coverage, {} measurements", view.coverage.len()));
+    coverage(ui, view, width);
+}
+
+fn plot_area(ui: &mut egui::Ui, width: f32, height: f32) -> (Rect, egui::Painter) {
+    let (response, painter) = ui.allocate_painter(Vec2::new(width, height), Sense::hover());
+    painter.rect_filled(response.rect, 4.0, BACKGROUND);
+    (response.rect, painter)
+}
+
+fn line(painter: &egui::Painter, points: Vec<Pos2>, color: Color32) {
+    painter.add(Shape::line(points, Stroke::new(1.5, color)));
+}
+
+fn impulse_responses(ui: &mut egui::Ui, view: &HrtfView, width: f32) {
+    let (rect, painter) = plot_area(ui, width, 120.0);
+    let peak = view
+        .left
+        .iter()
+        .chain(&view.right)
+        .fold(f32::EPSILON, |peak, s| peak.max(s.abs()));
+    let len = view.left.len() as f32;
+
+    let points = |ir: &[f32]| {
+        ir.iter()
+            .enumerate()
+            .map(|(i, s)| {
+                Pos2::new(
+                    rect.left() + rect.width() * i as f32 / len,
+                    rect.center().y - s / peak * rect.height() * 0.45,
+                )
+            })
+            .collect()
+    };
+    painter.line_segment(
+        [Pos2::new(rect.left(), rect.center().y), Pos2::new(rect.right(), rect.center().y)],
+        Stroke::new(1.0, GRID),
+    );
+    line(&painter, points(&view.left), LEFT);
+    line(&painter, points(&view.right), RIGHT);
+}
+
+fn magnitudes(ui: &mut egui::Ui, spectra: &Spectra, sample_rate: f32, width: f32) {
+    let (rect, painter) = plot_area(ui, width, 160.0);
+    let top = spectra
+        .left
+        .iter()
+        .chain(&spectra.right)
+        .fold(f32::MIN, |top, db| top.max(*db))
+        .ceil();
+
+    // log frequency axis from 20 Hz to Nyquist
+    let nyquist = sample_rate / 2.0;
+    let x = |freq: f32| {
+        rect.left() + rect.width() * (freq / 20.0).ln() / (nyquist / 20.0).ln()
+    };
+    for freq in [100.0, 1000.0, 10000.0] {
+        painter.line_segment(
+            [Pos2::new(x(freq), rect.top()), Pos2::new(x(freq), rect.bottom())],
+            Stroke::new(1.0, GRID),
+        );
+        painter.text(
+            Pos2::new(x(freq) + 2.0, rect.bottom() - 2.0),
+            Align2::LEFT_BOTTOM,
+            format!("{} Hz", freq),
+            FontId::proportional(10.0),
+            GRID,
+        );
+    }
+    painter.text(
+        rect.left_top() + Vec2::splat(4.0),
+        Align2::LEFT_TOP,
+        format!("{top:.0} dB"),
+        FontId::proportional(10.0),
+        GRID,
+    );
+
+    let points = |db: &[f32]| {
+        let bin_width = nyquist / (db.len() - 1) as f32;
+        db.iter()
+            .enumerate()
+            .skip_while(|(bin, _)| (*bin as f32) * bin_width < 20.0)
+            .map(|(bin, db)| {
+                let level = ((top - db) / DB_RANGE).clamp(0.0, 1.0);
+                Pos2::new(x(bin as f32 * bin_width), rect.top() + rect.height() * level)
+            })
+            .collect()
+    };
+    line(&painter, points(&spectra.left), LEFT);
+    line(&painter, points(&spectra.right), RIGHT);
+}
+
+/// Equirectangular projection, azimuth 180 (behind) to -180 from left to right so the front is in
+/// the middle and left is left
+fn coverage(ui: &mut egui::Ui, view: &HrtfView, width: f32) {
+    let height = (width / 2.0).min(200.0);
+    let (rect, painter) = plot_area(ui, width, height);
+    let project = |azimuth: f32, elevation: f32| {
+        let azimuth = (azimuth + 180.0).rem_euclid(360.0) - 180.0;
+        Pos2::new(
+            rect.center().x - azimuth / 360.0 * rect.width(),
+            rect.center().y - elevation / 180.0 * rect.height(),
+        )
+    };
+
+    painter.line_segment(
+        [Pos2::new(rect.left(), rect.center().y), Pos2::new(rect.right(), rect.center().y)],
+        Stroke::new(1.0, GRID),
+    );
+    painter.line_segment(
+        [Pos2::new(rect.center().x, rect.top()), Pos2::new(rect.center().x, rect.bottom())],
+        Stroke::new(1.0, GRID),
+    );
+    for &(azimuth, elevation) in &view.coverage {
+        painter.circle_filled(project(azimuth, elevation), 1.5, POINT);
+    }
+
+    let (azimuth, elevation, _) = view.position;
+    painter.circle_stroke(project(azimuth, elevation), 6.0, Stroke::new(2.0, SOURCE));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    #[test]
+    fn test_magnitude_db() {
+        // a unit impulse is flat at 0 dB, halving it is -6 dB
+        let mut ir = vec![0.0; 128];
+        ir[3] = 1.0;
+        let db = magnitude_db(&ir);
+        assert_eq!(db.len(), 512 / 2 + 1);
+        for bin in &db {
+            assert_approx_eq!(*bin, 0.0, 1e-3);
+        }
+
+        ir[3] = 0.5;
+        assert_approx_eq!(magnitude_db(&ir)[100], -6.0206, 1e-3);
+    }
+}
@@ -1,14 +1,76 @@
-use sofar::reader::{Filter, OpenOptions, Sofar};
-use sofar::render::Renderer;
-
 use nih_plug::prelude::*;
-use std::sync::Arc;
+use nih_plug::wrapper::state::PluginState;
+use nih_plug_egui::EguiState;
+use parking_lot::Mutex;
+use std::sync::{Arc, OnceLock, RwLock};
+
+pub mod audio_file;
+pub mod automation;
+pub mod doppler;
+mod editor;
+#[cfg(any(test, feature = "fixtures"))]
+pub mod fixtures;
+pub mod head_model;
+#[cfg(test)]
+mod headless;
+pub mod headphone_eq;
+pub mod hrtf_backend;
+pub mod hrtf_view;
+pub mod meters;
+pub mod migration;
+pub mod ola;
+pub mod presets;
+pub mod sofa_info;
+pub mod sofa_writer;
+pub mod spatializer_efx;
+pub mod trajectory;
+
+use automation::Automation;
+use headphone_eq::{EqProfile, HeadphoneEq};
+use hrtf_view::HrtfView;
+use meters::{MeterState, Meters};
+use migration::STATE_VERSION;
+use spatializer_efx::{ConvolutionEngine, Error, SpatializerEfx, MAX_DISTANCE, MIN_DISTANCE};
+use trajectory::{NoteDivision, Shape, TransportState};
+
+const SOFA_PATH: &str = "/Users/Owen/Documents/GitHub/ase-project/SOFA-data/HRIR_FULL2DEG.sofa";
+
+/// Defaults for new plugin instances that differ from the built-in ones. The standalone binary sets
+/// these from its command line before nih_plug creates the plugin.
+#[derive(Debug, Clone, Default)]
+pub struct StandaloneConfig {
+    pub sofa_path: Option<String>,
+    /// Headphone EQ profile, an FIR `.wav` or an AutoEQ `ParametricEQ.txt`
+    pub hp_eq_path: Option<String>,
+    /// Azimuth and elevation in degrees, distance in metres
+    pub position: Option<(f32, f32, f32)>,
+}
+
+static STANDALONE_CONFIG: OnceLock<StandaloneConfig> = OnceLock::new();
 
+/// Only the first call has any effect, and only on plugin instances created after it.
+pub fn set_standalone_config(config: StandaloneConfig) {
+    let _ = STANDALONE_CONFIG.set(config);
+}
 
-struct Spatializer {
+pub struct Spatializer {
     params: Arc<SpatializerParams>,
-    sofa: Sofar,
-    render: Renderer,
+    /// The DSP core, `None` when the dataset failed to load. The plugin then passes audio through
+    /// untouched.
+    efx: Option<SpatializerEfx>,
+    /// Why the last SOFA dataset could not be loaded, for displaying it instead of panicking
+    sofa_error: Arc<Mutex<Option<Error>>>,
+    /// Why the selected headphone EQ profile could not be loaded
+    hp_eq_error: Arc<Mutex<Option<Error>>>,
+    /// The filter in use and the dataset coverage, for the editor's HRTF view
+    hrtf_view: Arc<Mutex<HrtfView>>,
+    /// The filter generation last copied to `hrtf_view`
+    shown_generation: u64,
+    /// Level and interaural meters, updated after every block
+    meters: Arc<Meters>,
+    meter_state: MeterState,
+    /// The latency last reported to the host, in samples
+    latency: u32,
 }
 
 /// The [`Params`] derive macro gathers all of the information needed for the wrapper to know about
@@ -17,18 +79,66 @@ struct Spatializer {
 /// of a parameters struct for multiple identical oscillators/filters/envelopes.
 #[derive(Params)]
 struct SpatializerParams {
+    /// The editor window size
+    #[persist = "editor-state"]
+    editor_state: Arc<EguiState>,
+    /// The parameter layout this state was saved with, see [`migration`].
+    #[persist = "state-version"]
+    state_version: RwLock<u32>,
+
     /// The parameter's ID is used to identify the parameter in the wrapped plugin API. As long as
     /// these IDs remain constant, you can rename and reorder these fields as you wish. The
     /// parameters are exposed to the host in the same order they were defined. In this case, this
     /// gain parameter is stored as linear gain while the values are displayed in decibels.
     #[id = "gain"]
     pub gain: FloatParam,
-    #[id = "FrontBack"]
-    pub frontback: FloatParam,
-    #[id = "LeftRight"]
-    pub leftright: FloatParam,
-    #[id = "UpDown"]
-    pub updown: FloatParam,
+    /// Source position in SOFA spherical coordinates, 90 degrees azimuth is left.
+    #[id = "Azimuth"]
+    pub azimuth: FloatParam,
+    #[id = "Elevation"]
+    pub elevation: FloatParam,
+    #[id = "Distance"]
+    pub distance: FloatParam,
+    /// Rescales the ITD of the measured HRIRs to the listener's head, see [`head_model`].
+    #[id = "HeadSize"]
+    pub head_size: FloatParam,
+    /// Also shift the pinna notches by stretching the HRIRs instead of only changing the ITD.
+    #[id = "PinnaShift"]
+    pub pinna_shift: BoolParam,
+    /// How much of the real propagation delay is applied, sweeping it gives the Doppler shift.
+    #[id = "DopplerAmount"]
+    pub doppler_amount: FloatParam,
+    #[id = "SpeedOfSound"]
+    pub speed_of_sound: FloatParam,
+    #[id = "HpEqBypass"]
+    pub hp_eq_bypass: BoolParam,
+    /// Which convolution backend renders the HRIRs, see [`ConvolutionEngine`].
+    #[id = "Engine"]
+    pub engine: EnumParam<ConvolutionEngine>,
+    /// Path to the headphone EQ profile, either an FIR `.wav` or an AutoEQ `ParametricEQ.txt`.
+    #[persist = "headphone-eq-path"]
+    pub hp_eq_path: RwLock<Option<String>>,
+    /// The HRTF dataset, `SOFA_PATH` when not set.
+    #[persist = "sofa-path"]
+    pub sofa_path: RwLock<Option<String>>,
+
+    /// Automatic motion around the position set above, see [`trajectory`].
+    #[id = "Motion"]
+    pub motion: EnumParam<Shape>,
+    #[id = "MotionRate"]
+    pub motion_rate: FloatParam,
+    /// Use `motion_division` at the host tempo instead of `motion_rate`
+    #[id = "MotionSync"]
+    pub motion_sync: BoolParam,
+    #[id = "MotionDivision"]
+    pub motion_division: EnumParam<NoteDivision>,
+    #[id = "MotionPhase"]
+    pub motion_phase: FloatParam,
+    #[id = "MotionWidth"]
+    pub motion_width: FloatParam,
+    /// Keyframe file followed by the path motion, in the same format as `ase-render` uses.
+    #[persist = "motion-path"]
+    pub motion_path: RwLock<Option<String>>,
 }
 
 ///================================================================================================///
@@ -36,15 +146,27 @@ impl Default for Spatializer {
     fn default() -> Self {      
         Self {
             params: Arc::new(SpatializerParams::default()),
-            sofa: OpenOptions::new().open("/Users/Owen/Documents/GitHub/ase-project/SOFA-data/HRIR_FULL2DEG.sofa").unwrap(),
-            render: Renderer::builder(128).build().unwrap(),
+            efx: None,
+            sofa_error: Arc::new(Mutex::new(None)),
+            hp_eq_error: Arc::new(Mutex::new(None)),
+            hrtf_view: Arc::new(Mutex::new(HrtfView::default())),
+            shown_generation: 0,
+            meters: Arc::new(Meters::default()),
+            meter_state: MeterState::new(44100.0),
+            latency: 0,
         }
     }
 }
 
 impl Default for SpatializerParams {
     fn default() -> Self {
+        let config = STANDALONE_CONFIG.get().cloned().unwrap_or_default();
+        let (azimuth, elevation, distance) = config.position.unwrap_or((0.0, 0.0, 1.0));
+
         Self {
+            editor_state: editor::default_state(),
+            state_version: RwLock::new(STATE_VERSION),
+
             // This gain is stored as linear gain. NIH-plug comes with useful conversion functions
             // to treat these kinds of parameters as if we were dealing with decibels. Storing this
             // as decibels is easier to work with, but requires a conversion for every sample.
@@ -69,30 +191,107 @@ impl Default for SpatializerParams {
             .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
             .with_string_to_value(formatters::s2v_f32_gain_to_db()),
 
-            frontback: FloatParam::new(
-                "Front-Back",
-                0.0,
+            azimuth: FloatParam::new(
+                "Azimuth",
+                (azimuth + 180.0).rem_euclid(360.0) - 180.0,
                 FloatRange::Linear { min: -180.0, max: 180.0 },
             )
             .with_unit(" deg")
-            .with_smoother(SmoothingStyle::Linear(50.0)),
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
 
-            leftright: FloatParam::new(
-                "Left-Right",
-                0.0,
-                FloatRange::Linear { min: -180.0, max: 180.0 },
+            elevation: FloatParam::new(
+                "Elevation",
+                elevation.clamp(-90.0, 90.0),
+                FloatRange::Linear { min: -90.0, max: 90.0 },
             )
             .with_unit(" deg")
-            .with_smoother(SmoothingStyle::Linear(50.0)),
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
 
-            updown: FloatParam::new(
-                "Up-Down",
+            distance: FloatParam::new(
+                "Distance",
+                distance.clamp(MIN_DISTANCE, MAX_DISTANCE),
+                FloatRange::Skewed {
+                    min: MIN_DISTANCE,
+                    max: MAX_DISTANCE,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_unit(" m")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            // Head circumference in cm, the reference size leaves the dataset untouched
+            head_size: FloatParam::new(
+                "Head Size",
+                head_model::REFERENCE_CIRCUMFERENCE,
+                FloatRange::Linear {
+                    min: head_model::MIN_CIRCUMFERENCE,
+                    max: head_model::MAX_CIRCUMFERENCE,
+                },
+            )
+            .with_unit(" cm")
+            .with_step_size(0.1),
+
+            pinna_shift: BoolParam::new("Pinna Notch Shift", false),
+
+            // off by default, the propagation delay would also delay the whole signal
+            doppler_amount: FloatParam::new(
+                "Doppler Amount",
                 0.0,
-                FloatRange::Linear { min: -180.0, max: 180.0 },
+                FloatRange::Linear { min: 0.0, max: 1.0 },
             )
-            .with_unit(" deg")
-            .with_smoother(SmoothingStyle::Linear(50.0)),               
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+
+            speed_of_sound: FloatParam::new(
+                "Speed of Sound",
+                head_model::SPEED_OF_SOUND,
+                FloatRange::Linear {
+                    min: doppler::MIN_SPEED_OF_SOUND,
+                    max: doppler::MAX_SPEED_OF_SOUND,
+                },
+            )
+            .with_unit(" m/s")
+            .with_step_size(1.0),
+
+            hp_eq_bypass: BoolParam::new("Headphone EQ Bypass", false),
+            engine: EnumParam::new("Convolution", ConvolutionEngine::Sofar),
+            hp_eq_path: RwLock::new(config.hp_eq_path),
+            sofa_path: RwLock::new(config.sofa_path),
+
+            motion: EnumParam::new("Motion", Shape::Static),
 
+            // cycles per second
+            motion_rate: FloatParam::new(
+                "Motion Rate",
+                0.1,
+                FloatRange::Skewed {
+                    min: 0.01,
+                    max: 10.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_unit(" Hz")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            motion_sync: BoolParam::new("Tempo Sync", false),
+            motion_division: EnumParam::new("Motion Division", NoteDivision::Bar),
+
+            motion_phase: FloatParam::new(
+                "Motion Phase",
+                0.0,
+                FloatRange::Linear { min: 0.0, max: 360.0 },
+            )
+            .with_unit(" deg"),
+
+            motion_width: FloatParam::new(
+                "Motion Width",
+                45.0,
+                FloatRange::Linear { min: 0.0, max: 180.0 },
+            )
+            .with_unit(" deg"),
+
+            motion_path: RwLock::new(None),
         }
     }
 }
@@ -139,70 +338,196 @@ impl Plugin for Spatializer {
         self.params.clone()
     }
 
+    fn filter_state(state: &mut PluginState) {
+        migration::migrate(state);
+    }
+
+    fn editor(&mut self, _async_executor: AsyncExecutor<Self>) -> Option<Box<dyn Editor>> {
+        editor::create(
+            self.params.clone(),
+            self.params.editor_state.clone(),
+            self.hrtf_view.clone(),
+            self.meters.clone(),
+            self.hp_eq_error.clone(),
+        )
+    }
+
     fn initialize(
         &mut self,
         _audio_io_layout: &AudioIOLayout,
-        _buffer_config: &BufferConfig,
-        _context: &mut impl InitContext<Self>,
+        buffer_config: &BufferConfig,
+        context: &mut impl InitContext<Self>,
     ) -> bool {
 
-        // load in sofa dataset 
-        let sofa = OpenOptions::new()
-            .sample_rate(48000.0)
-            .open("/Users/Owen/Documents/GitHub/ase-project/SOFA-data/HRIR_FULL2DEG.sofa")
-            .unwrap(); 
-        self.sofa = sofa;
-        
-        // create method for calculating the convolution
-        let mut render = Renderer::builder(self.sofa.filter_len())
-            .with_sample_rate(44100.0)
-            .with_partition_len(64)
-            .build()
-            .unwrap();
-        self.render = render;
+        self.meter_state = MeterState::new(buffer_config.sample_rate);
+
+        // load in sofa dataset, a broken or unsupported file leaves the plugin in pass-through
+        self.efx = None;
+        self.latency = 0;
+        context.set_latency_samples(0);
+        let sofa_path = self.params.sofa_path.read().unwrap().clone();
+        let sofa_path = sofa_path.as_deref().unwrap_or(SOFA_PATH);
+        let efx = hrtf_backend::open(sofa_path, buffer_config.sample_rate).map(|backend| {
+            SpatializerEfx::with_backend(
+                backend,
+                buffer_config.sample_rate,
+                buffer_config.max_buffer_size as usize,
+            )
+        });
+        let mut efx = match efx {
+            Ok(efx) => efx,
+            Err(err) => {
+                nih_error!("Failed to load HRTF dataset: {err}");
+                *self.sofa_error.lock() = Some(err);
+                return true;
+            }
+        };
+        *self.sofa_error.lock() = None;
+        self.hrtf_view
+            .lock()
+            .load(sofa_path, buffer_config.sample_rate, efx.filter_len());
+        self.shown_generation = 0;
+
+        // headphone compensation after the HRTF render
+        *self.hp_eq_error.lock() = None;
+        let hp_eq_path = self.params.hp_eq_path.read().unwrap().clone();
+        if let Some(path) = hp_eq_path {
+            match EqProfile::load(&path)
+                .and_then(|profile| HeadphoneEq::new(&profile, buffer_config.sample_rate))
+            {
+                Ok(hp_eq) => efx.set_headphone_eq(Some(hp_eq)),
+                Err(err) => {
+                    nih_error!("Failed to load headphone EQ: {err}");
+                    *self.hp_eq_error.lock() = Some(err);
+                }
+            }
+        }
+
+        // keyframes for the path motion
+        let motion_path = self.params.motion_path.read().unwrap().clone();
+        if let Some(path) = motion_path {
+            match Automation::load(&path) {
+                Ok(automation) => efx.trajectory_mut().set_path(Some(automation)),
+                Err(err) => nih_error!("Failed to load motion path {path}: {err}"),
+            }
+        }
+
+        efx.set_engine(self.params.engine.value());
+        efx.set_head_size(self.params.head_size.value(), self.params.pinna_shift.value());
+        efx.set_headphone_eq_bypass(self.params.hp_eq_bypass.value());
+        self.latency = efx.latency_samples();
+        context.set_latency_samples(self.latency);
+
+        self.efx = Some(efx);
 
         true
     }    
 
+    fn reset(&mut self) {
+        if let Some(efx) = &mut self.efx {
+            efx.reset();
+        }
+        self.meter_state.reset();
+    }
+
     fn process(
         &mut self,
         buffer: &mut Buffer,
         _aux: &mut AuxiliaryBuffers,
-        _context: &mut impl ProcessContext<Self>,
+        context: &mut impl ProcessContext<Self>,
     ) -> ProcessStatus {
+        self.process_block(buffer, &NihHost(&*context));
+
+        ProcessStatus::Normal
+    }
+
+    // This can be used for cleaning up special resources like socket connections whenever the
+    // plugin is deactivated. Most plugins won't need to do anything here.
+    fn deactivate(&mut self) {}
+}
+
+/// The host as `process_block` sees it, so the headless tests can play the host without building
+/// a nih_plug `Transport`.
+trait BlockHost {
+    /// The transport at the start of the block.
+    fn transport(&self) -> TransportState;
+
+    fn set_latency_samples(&self, samples: u32);
+}
+
+/// nih_plug's process context as a [`BlockHost`].
+struct NihHost<'a, C>(&'a C);
+
+impl<C: ProcessContext<Spatializer>> BlockHost for NihHost<'_, C> {
+    fn transport(&self) -> TransportState {
+        transport_state(self.0.transport())
+    }
+
+    fn set_latency_samples(&self, samples: u32) {
+        self.0.set_latency_samples(samples);
+    }
+}
+
+impl Spatializer {
+    /// Everything `process` does, with the host behind `host`.
+    fn process_block(&mut self, buffer: &mut Buffer, host: &impl BlockHost) {
+        if let [left, right, ..] = buffer.as_slice_immutable() {
+            self.meter_state.add_input(left, right);
+        }
+
+        // nothing to render with, pass the input through
+        let Some(efx) = &mut self.efx else {
+            self.update_meters(buffer);
+            return;
+        };
+
+        efx.set_spherical_position(
+            self.params.azimuth.value(),
+            self.params.elevation.value(),
+            self.params.distance.value(),
+        );
+
+        efx.set_engine(self.params.engine.value());
+
+        // personalise the ITD (and optionally the pinna notches) to the listener's head size
+        efx.set_head_size(self.params.head_size.value(), self.params.pinna_shift.value());
+        efx.set_headphone_eq_bypass(self.params.hp_eq_bypass.value());
+        efx.set_doppler(
+            self.params.doppler_amount.value(),
+            self.params.speed_of_sound.value(),
+        );
+
+        // the engine and headphone EQ settings move the onset of the direct sound
+        if efx.latency_samples() != self.latency {
+            self.latency = efx.latency_samples();
+            host.set_latency_samples(self.latency);
+        }
+
+        // automatic motion, advanced sample by sample inside `efx.process`
+        let trajectory = efx.trajectory_mut();
+        trajectory.set_shape(self.params.motion.value());
+        trajectory.set_phase(self.params.motion_phase.value());
+        trajectory.set_width(self.params.motion_width.value());
+        if self.params.motion_sync.value() {
+            // locked to the song position so the motion repeats exactly on every playback
+            efx.sync_trajectory(&host.transport(), self.params.motion_division.value());
+        } else {
+            trajectory.set_rate_hz(self.params.motion_rate.value());
+        }
+
+        // render binaurally and modify the buffer in-place
+        efx.process(buffer.as_slice());
+
+        // share the new filter with the editor, skipped if it is drawing right now
+        if efx.filter_generation() != self.shown_generation {
+            if let Some(mut view) = self.hrtf_view.try_lock() {
+                if let Some(filter) = efx.filter() {
+                    view.update(filter, efx.rendered_position());
+                }
+                self.shown_generation = efx.filter_generation();
+            }
+        }
 
-        let buffer_slice: &mut [&mut [f32]] = buffer.as_slice();
-
-        // Mono processing: Combine the channels with average between corresponding rows
-        let combined_buffer: Vec<_> = buffer_slice[0]
-            .iter()
-            .zip(buffer_slice[1].iter())
-            .map(|(row1, row2)| (*row1 + *row2) / 2.0) // mono_sample.clamp(-1.0, 1.0)
-            .collect();
-
-        // The x,y,z parameter only can take in range from -1 to 1
-        // add 0.001 offset because there is no IR at position 0.0, 0.0 , 0.0
-        let x = self.params.frontback.value() / -180.0 + 0.001;  // front-back
-        let y = self.params.leftright.value() / -180.0 + 0.001;  // right-left
-        let z = self.params.updown.value() / -180.0 + 0.001;     // up-down 
-        let filt_len = self.sofa.filter_len();
-        let mut filter = Filter::new(filt_len);
-
-        // extract the IR, both left and right channels
-        self.sofa.filter(x, y, z, &mut filter);
-        
-        // feed IR to render
-        self.render.set_filter(&filter);
-
-        let mut left: Vec<f32> = vec![0.0; combined_buffer.len()];
-        let mut right: Vec<f32> = vec![0.0; combined_buffer.len()];
-        self.render
-            .process_block(&combined_buffer, &mut left, &mut right)
-            .unwrap();
-
-        // Modify the buffer in-place
-        buffer_slice[0].copy_from_slice(&left);
-        buffer_slice[1].copy_from_slice(&right);
         for channel_samples in buffer.iter_samples() {
             // Smoothing is optionally built into the parameters themselves
             let gain = self.params.gain.smoothed.next();
@@ -210,13 +535,49 @@ impl Plugin for Spatializer {
                 *sample *= gain;
             }
         } 
-  
-        ProcessStatus::Normal
+
+        self.update_meters(buffer);
     }
 
-    // This can be used for cleaning up special resources like socket connections whenever the
-    // plugin is deactivated. Most plugins won't need to do anything here.
-    fn deactivate(&mut self) {}
+    /// The latest meter readings, updated after every processed block.
+    pub fn meters(&self) -> Arc<Meters> {
+        self.meters.clone()
+    }
+
+    fn update_meters(&mut self, buffer: &Buffer) {
+        if let [left, right, ..] = buffer.as_slice_immutable() {
+            self.meter_state.add_output(left, right);
+        }
+        self.meter_state.publish(&self.meters);
+    }
+}
+
+impl SpatializerParams {
+    /// The source position in SOFA cartesian coordinates.
+    fn position(&self) -> (f32, f32, f32) {
+        spatializer_efx::spherical_to_cartesian(
+            self.azimuth.value(),
+            self.elevation.value(),
+            self.distance.value(),
+        )
+    }
+}
+
+/// The parts of the host transport the trajectory follows, with defaults for whatever the host
+/// doesn't report.
+fn transport_state(transport: &Transport) -> TransportState {
+    let beats_per_bar = match (transport.time_sig_numerator, transport.time_sig_denominator) {
+        (Some(numerator), Some(denominator)) => numerator as f64 * 4.0 / denominator as f64,
+        _ => 4.0,
+    };
+
+    TransportState {
+        playing: transport.playing,
+        tempo: transport.tempo.unwrap_or(120.0),
+        beats_per_bar,
+        pos_beats: transport.pos_beats(),
+        bar: transport.bar_number().zip(transport.bar_start_pos_beats()),
+    }
 }
 
 impl ClapPlugin for Spatializer {
@@ -0,0 +1,166 @@
+// Level and interaural meters, computed on the audio thread and published through atomics so the
+// editor or anything else holding the `Arc<Meters>` can read them without locking
+
+use nih_plug::prelude::*;
+
+use std::sync::atomic::Ordering;
+
+/// Time constant of the RMS, ILD and correlation averages in seconds
+const AVERAGE_TIME: f32 = 0.3;
+/// Time constant of the peak meter's fall back
+const PEAK_DECAY_TIME: f32 = 1.0;
+
+/// The latest meter readings. Levels are in dBFS, the ILD in dB (positive when the left ear is
+/// louder) and the interaural correlation between -1 and 1.
+#[derive(Debug)]
+pub struct Meters {
+    pub input_peak: AtomicF32,
+    pub input_rms: AtomicF32,
+    pub left_peak: AtomicF32,
+    pub left_rms: AtomicF32,
+    pub right_peak: AtomicF32,
+    pub right_rms: AtomicF32,
+    pub ild: AtomicF32,
+    pub correlation: AtomicF32,
+}
+
+impl Default for Meters {
+    fn default() -> Self {
+        let silence = util::MINUS_INFINITY_DB;
+        Meters {
+            input_peak: AtomicF32::new(silence),
+            input_rms: AtomicF32::new(silence),
+            left_peak: AtomicF32::new(silence),
+            left_rms: AtomicF32::new(silence),
+            right_peak: AtomicF32::new(silence),
+            right_rms: AtomicF32::new(silence),
+            ild: AtomicF32::new(0.0),
+            correlation: AtomicF32::new(0.0),
+        }
+    }
+}
+
+/// Peak with an exponential fall back and a mean square, for one signal
+#[derive(Debug, Clone, Copy, Default)]
+struct Level {
+    peak: f32,
+    mean_square: f32,
+}
+
+impl Level {
+    fn add(&mut self, sample: f32, average: f32, decay: f32) {
+        self.peak = sample.abs().max(self.peak * decay);
+        self.mean_square += (sample * sample - self.mean_square) * average;
+    }
+
+    fn publish(&self, peak: &AtomicF32, rms: &AtomicF32) {
+        peak.store(util::gain_to_db(self.peak), Ordering::Relaxed);
+        rms.store(util::gain_to_db(self.mean_square.sqrt()), Ordering::Relaxed);
+    }
+}
+
+/// The running averages behind [`Meters`], owned by the audio thread.
+#[derive(Debug, Clone)]
+pub struct MeterState {
+    /// Smoothing coefficients per sample
+    average: f32,
+    decay: f32,
+
+    input: Level,
+    left: Level,
+    right: Level,
+    /// Mean of left times right, for the correlation
+    cross: f32,
+}
+
+impl MeterState {
+    pub fn new(sample_rate: f32) -> Self {
+        MeterState {
+            average: 1.0 - (-1.0 / (AVERAGE_TIME * sample_rate)).exp(),
+            decay: (-1.0 / (PEAK_DECAY_TIME * sample_rate)).exp(),
+            input: Level::default(),
+            left: Level::default(),
+            right: Level::default(),
+            cross: 0.0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.input = Level::default();
+        self.left = Level::default();
+        self.right = Level::default();
+        self.cross = 0.0;
+    }
+
+    /// Measure the mono input the renderer sees, the average of the input channels.
+    pub fn add_input(&mut self, left: &[f32], right: &[f32]) {
+        for (l, r) in left.iter().zip(right) {
+            self.input.add((l + r) / 2.0, self.average, self.decay);
+        }
+    }
+
+    /// Measure the binaural output.
+    pub fn add_output(&mut self, left: &[f32], right: &[f32]) {
+        for (&l, &r) in left.iter().zip(right) {
+            self.left.add(l, self.average, self.decay);
+            self.right.add(r, self.average, self.decay);
+            self.cross += (l * r - self.cross) * self.average;
+        }
+    }
+
+    pub fn publish(&self, meters: &Meters) {
+        self.input.publish(&meters.input_peak, &meters.input_rms);
+        self.left.publish(&meters.left_peak, &meters.left_rms);
+        self.right.publish(&meters.right_peak, &meters.right_rms);
+
+        let (left, right) = (self.left.mean_square, self.right.mean_square);
+        let ild = util::gain_to_db(left.sqrt()) - util::gain_to_db(right.sqrt());
+        let correlation = if left > 1e-12 && right > 1e-12 {
+            (self.cross / (left * right).sqrt()).clamp(-1.0, 1.0)
+        } else {
+            0.0
+        };
+        meters.ild.store(ild, Ordering::Relaxed);
+        meters.correlation.store(correlation, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    fn sine(len: usize, amplitude: f32) -> Vec<f32> {
+        (0..len)
+            .map(|i| amplitude * (i as f32 * 0.05).sin())
+            .collect()
+    }
+
+    #[test]
+    fn test_levels_and_interaural() {
+        let meters = Meters::default();
+        let mut state = MeterState::new(48000.0);
+        let left = sine(3 * 48000, 1.0);
+        let right = sine(3 * 48000, 0.5);
+
+        // long enough for the averages to settle
+        state.add_input(&left, &left);
+        state.add_output(&left, &right);
+        state.publish(&meters);
+
+        assert_approx_eq!(meters.input_peak.load(Ordering::Relaxed), 0.0, 0.01);
+        // the RMS of a sine is 3 dB below its peak
+        assert_approx_eq!(meters.left_rms.load(Ordering::Relaxed), -3.01, 0.1);
+        assert_approx_eq!(meters.right_peak.load(Ordering::Relaxed), -6.02, 0.01);
+        assert_approx_eq!(meters.ild.load(Ordering::Relaxed), 6.02, 0.1);
+        assert_approx_eq!(meters.correlation.load(Ordering::Relaxed), 1.0, 0.01);
+
+        // opposite polarity
+        let inverted: Vec<f32> = left.iter().map(|s| -s).collect();
+        state.reset();
+        state.add_output(&left, &inverted);
+        state.publish(&meters);
+        assert_approx_eq!(meters.correlation.load(Ordering::Relaxed), -1.0, 0.01);
+        assert_approx_eq!(meters.ild.load(Ordering::Relaxed), 0.0, 0.01);
+    }
+}
@@ -0,0 +1,145 @@
+// Upgrades saved plugin states (host sessions and presets) from older versions of the parameter
+// layout. Every state records its layout in the `state-version` persistent field, states saved
+// before that field existed are version 1.
+//
+// Versions:
+//   1. Position as "FrontBack", "LeftRight" and "UpDown", -180..180 mapped to the SOFA cartesian
+//      coordinates 1..-1 with a 0.001 offset
+//   2. Position as "Azimuth", "Elevation" (degrees) and "Distance" (metres)
+//
+// Only the stored values are converted. Host automation lanes recorded on removed parameters can't
+// be carried over.
+
+use nih_plug::prelude::*;
+use nih_plug::wrapper::state::{ParamValue, PluginState};
+
+use crate::spatializer_efx::{cartesian_to_spherical, MAX_DISTANCE, MIN_DISTANCE};
+
+/// The version states written by this build have.
+pub const STATE_VERSION: u32 = 2;
+/// The persistent field holding the version, see `SpatializerParams`.
+pub const VERSION_FIELD: &str = "state-version";
+
+/// The version `state` was saved with.
+pub fn state_version(state: &PluginState) -> u32 {
+    state
+        .fields
+        .get(VERSION_FIELD)
+        .and_then(|value| serde_json::from_str(value).ok())
+        .unwrap_or(1)
+}
+
+/// Bring `state` up to [`STATE_VERSION`]. States from a newer build are left alone, nih_plug then
+/// restores whatever parameters it still recognises.
+pub fn migrate(state: &mut PluginState) {
+    let version = state_version(state);
+    if version > STATE_VERSION {
+        nih_warn!("State version {version} is newer than this build's {STATE_VERSION}");
+        return;
+    }
+
+    if version < 2 {
+        cartesian_to_spherical_params(state);
+    }
+
+    state
+        .fields
+        .insert(VERSION_FIELD.to_owned(), STATE_VERSION.to_string());
+}
+
+/// Version 1 to 2: replace Front-Back, Left-Right and Up-Down by the same position in spherical
+/// coordinates. A state without any of them keeps the new parameters' defaults.
+fn cartesian_to_spherical_params(state: &mut PluginState) {
+    let mut old = |id: &str| match state.params.remove(id) {
+        Some(ParamValue::F32(value)) => Some(value),
+        _ => None,
+    };
+    let (frontback, leftright, updown) = (old("FrontBack"), old("LeftRight"), old("UpDown"));
+    if frontback.is_none() && leftright.is_none() && updown.is_none() {
+        return;
+    }
+
+    // the version 1 mapping, a missing parameter was at its default of 0
+    let coord = |value: Option<f32>| value.unwrap_or(0.0) / -180.0 + 0.001;
+    let (azimuth, elevation, distance) =
+        cartesian_to_spherical(coord(frontback), coord(leftright), coord(updown));
+
+    for (id, value) in [
+        ("Azimuth", azimuth),
+        ("Elevation", elevation),
+        ("Distance", distance.clamp(MIN_DISTANCE, MAX_DISTANCE)),
+    ] {
+        state.params.insert(id.to_owned(), ParamValue::F32(value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    fn fixture(text: &str) -> PluginState {
+        let mut state: PluginState = serde_json::from_str(text).unwrap();
+        migrate(&mut state);
+        assert_eq!(state_version(&state), STATE_VERSION);
+        state
+    }
+
+    fn param(state: &PluginState, id: &str) -> f32 {
+        match state.params[id] {
+            ParamValue::F32(value) => value,
+            ref other => panic!("{id} is {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_v1_front() {
+        let state = fixture(include_str!("../fixtures/state_v1_front.json"));
+
+        for id in ["FrontBack", "LeftRight", "UpDown"] {
+            assert!(!state.params.contains_key(id));
+        }
+        assert_approx_eq!(param(&state, "Azimuth"), 0.0, 0.1);
+        assert_approx_eq!(param(&state, "Elevation"), 0.0, 0.1);
+        assert_approx_eq!(param(&state, "Distance"), 1.0, 0.01);
+        // everything else is kept
+        assert_approx_eq!(param(&state, "gain"), 0.5, 1e-6);
+        assert!(state.fields["sofa-path"].contains("HRIR_FULL2DEG.sofa"));
+    }
+
+    #[test]
+    fn test_v1_left_above() {
+        // half way to the left and up, the old mapping put that at 0.5 m on both axes
+        let state = fixture(include_str!("../fixtures/state_v1_left_above.json"));
+
+        assert_approx_eq!(param(&state, "Azimuth"), 90.0, 0.2);
+        assert_approx_eq!(param(&state, "Elevation"), 45.0, 0.2);
+        assert_approx_eq!(param(&state, "Distance"), 0.501 * 2f32.sqrt(), 0.01);
+    }
+
+    #[test]
+    fn test_v1_without_position() {
+        let state = fixture(include_str!("../fixtures/state_v1_gain_only.json"));
+
+        assert_eq!(state.params.len(), 1);
+        assert!(!state.params.contains_key("Azimuth"));
+    }
+
+    #[test]
+    fn test_current_and_newer_untouched() {
+        let mut state = fixture(include_str!("../fixtures/state_v1_front.json"));
+        let migrated = state.params.clone();
+        migrate(&mut state);
+        assert_eq!(state.params, migrated);
+
+        state
+            .fields
+            .insert(VERSION_FIELD.to_owned(), (STATE_VERSION + 1).to_string());
+        state
+            .params
+            .insert(String::from("FrontBack"), ParamValue::F32(90.0));
+        migrate(&mut state);
+        assert_eq!(state_version(&state), STATE_VERSION + 1);
+        assert!(state.params.contains_key("FrontBack"));
+    }
+}
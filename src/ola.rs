@@ -0,0 +1,424 @@
+// FFT overlap-add convolution of a mono signal with an HRIR pair, the alternative to
+// `sofar::render::Renderer`. The IRs are split into partitions of `block_len` taps whose spectra are
+// computed once per filter change. Every `block_len` input samples the block's spectrum goes into a
+// frequency domain delay line, gets multiplied with the matching partition spectra and the inverse
+// FFT is overlap-added to the output. A new filter is crossfaded in over one block, running the old
+// and the new filter side by side for that block. Everything is allocated in `new`.
+
+use realfft::num_complex::Complex32;
+use realfft::{ComplexToReal, RealFftPlanner, RealToComplex};
+use sofar::reader::Filter;
+
+use std::sync::Arc;
+
+/// Uniformly partitioned overlap-add convolver for one input and two ears. The output is delayed by
+/// one block, see [`latency_samples`](Self::latency_samples).
+pub struct OlaConvolver {
+    block_len: usize,
+    r2c: Arc<dyn RealToComplex<f32>>,
+    c2r: Arc<dyn ComplexToReal<f32>>,
+
+    /// Spectra of the last `partitions` input blocks, `block_len + 1` bins each
+    fdl: Vec<Complex32>,
+    /// Slot of the newest block in `fdl`
+    newest: usize,
+    /// The input block being filled
+    input: Vec<f32>,
+    /// Samples of `input` filled so far, also the read position in the ears' outputs
+    pos: usize,
+    /// Whether anything was rendered since `new` or `reset`, a new filter is applied right away
+    /// until then
+    playing: bool,
+    /// Whether the next block fades from the ears' `prev_spectra` to their `spectra`
+    fading: bool,
+
+    left: Ear,
+    right: Ear,
+    scratch: Scratch,
+}
+
+struct Ear {
+    /// Spectra of the IR partitions, in the same layout as the delay line
+    spectra: Vec<Complex32>,
+    /// The spectra and overlap of the filter being faded out
+    prev_spectra: Vec<Complex32>,
+    prev_overlap: Vec<f32>,
+    /// The output block being played back
+    output: Vec<f32>,
+    /// The second half of the last block's convolution, added to the next one
+    overlap: Vec<f32>,
+}
+
+struct Scratch {
+    /// `2 * block_len` samples, the FFT input and inverse FFT output
+    time: Vec<f32>,
+    /// Sum of the products of the delay line and partition spectra
+    sum: Vec<Complex32>,
+    fft: Vec<Complex32>,
+}
+
+impl OlaConvolver {
+    /// A convolver for IRs of up to `filter_len` taps, processed in partitions of `block_len`.
+    pub fn new(filter_len: usize, block_len: usize) -> Self {
+        let block_len = block_len.max(1);
+        let partitions = filter_len.div_ceil(block_len).max(1);
+        let bins = block_len + 1;
+
+        let mut planner = RealFftPlanner::<f32>::new();
+        let r2c = planner.plan_fft_forward(2 * block_len);
+        let c2r = planner.plan_fft_inverse(2 * block_len);
+        let scratch_len = r2c.get_scratch_len().max(c2r.get_scratch_len());
+
+        let ear = || Ear {
+            spectra: vec![Complex32::default(); partitions * bins],
+            prev_spectra: vec![Complex32::default(); partitions * bins],
+            prev_overlap: vec![0.0; block_len],
+            output: vec![0.0; block_len],
+            overlap: vec![0.0; block_len],
+        };
+
+        OlaConvolver {
+            block_len,
+            r2c,
+            c2r,
+            fdl: vec![Complex32::default(); partitions * bins],
+            newest: 0,
+            input: vec![0.0; block_len],
+            pos: 0,
+            playing: false,
+            fading: false,
+            left: ear(),
+            right: ear(),
+            scratch: Scratch {
+                time: vec![0.0; 2 * block_len],
+                sum: vec![Complex32::default(); bins],
+                fft: vec![Complex32::default(); scratch_len],
+            },
+        }
+    }
+
+    /// Use a new HRIR pair from the next block on, crossfading from the old one over that block so
+    /// a moving source doesn't click. Taps beyond the length given to `new` are ignored.
+    /// Real-time safe.
+    pub fn set_filter(&mut self, filter: &Filter) {
+        // several changes within a block fade from the filter that is still playing
+        let fade = self.playing && !self.fading;
+        for (ear, ir) in [
+            (&mut self.left, &filter.left),
+            (&mut self.right, &filter.right),
+        ] {
+            if fade {
+                ear.prev_spectra.copy_from_slice(&ear.spectra);
+                ear.prev_overlap.copy_from_slice(&ear.overlap);
+            }
+            ear.set_ir(ir, self.block_len, &*self.r2c, &mut self.scratch);
+            if self.playing {
+                ear.restart_overlap(&self.fdl, self.newest, &*self.c2r, &mut self.scratch);
+            }
+        }
+        self.fading = self.playing;
+    }
+
+    /// Convolve `input` with the filter. State is kept between calls, the output is `input`
+    /// convolved and delayed by [`latency_samples`](Self::latency_samples) no matter how the signal
+    /// is split into blocks.
+    pub fn process_block(&mut self, input: &[f32], left: &mut [f32], right: &mut [f32]) {
+        for ((&sample, left), right) in input.iter().zip(left).zip(right) {
+            self.input[self.pos] = sample;
+            *left = self.left.output[self.pos];
+            *right = self.right.output[self.pos];
+
+            self.pos += 1;
+            if self.pos == self.block_len {
+                self.process_partition();
+                self.pos = 0;
+            }
+        }
+    }
+
+    /// Clear the input history and the convolution tail.
+    pub fn reset(&mut self) {
+        self.fdl.fill(Complex32::default());
+        self.input.fill(0.0);
+        self.pos = 0;
+        self.playing = false;
+        self.fading = false;
+        for ear in [&mut self.left, &mut self.right] {
+            ear.output.fill(0.0);
+            ear.overlap.fill(0.0);
+        }
+    }
+
+    /// Input samples are collected for a whole block before it is convolved.
+    pub fn latency_samples(&self) -> u32 {
+        self.block_len as u32
+    }
+
+    fn process_partition(&mut self) {
+        let bins = self.block_len + 1;
+        let partitions = self.fdl.len() / bins;
+
+        // the oldest block drops out of the delay line
+        self.newest = (self.newest + 1) % partitions;
+        let time = &mut self.scratch.time;
+        time[..self.block_len].copy_from_slice(&self.input);
+        time[self.block_len..].fill(0.0);
+        self.r2c
+            .process_with_scratch(
+                time,
+                &mut self.fdl[self.newest * bins..(self.newest + 1) * bins],
+                &mut self.scratch.fft,
+            )
+            .unwrap();
+
+        for ear in [&mut self.left, &mut self.right] {
+            ear.render(
+                &self.fdl,
+                self.newest,
+                self.fading,
+                &*self.c2r,
+                &mut self.scratch,
+            );
+        }
+        self.playing = true;
+        self.fading = false;
+    }
+}
+
+impl Ear {
+    fn set_ir(
+        &mut self,
+        ir: &[f32],
+        block_len: usize,
+        r2c: &dyn RealToComplex<f32>,
+        scratch: &mut Scratch,
+    ) {
+        let bins = block_len + 1;
+        for (partition, spectrum) in self.spectra.chunks_exact_mut(bins).enumerate() {
+            let start = (partition * block_len).min(ir.len());
+            let taps = &ir[start..(start + block_len).min(ir.len())];
+
+            let time = &mut scratch.time;
+            time[..taps.len()].copy_from_slice(taps);
+            time[taps.len()..].fill(0.0);
+            r2c.process_with_scratch(time, spectrum, &mut scratch.fft).unwrap();
+        }
+    }
+
+    /// Convolve the delay line with the IR partitions and overlap-add the result into `output`.
+    /// With `fade` the output goes from what the previous IR would have given to the new IR's over
+    /// the block.
+    fn render(
+        &mut self,
+        fdl: &[Complex32],
+        newest: usize,
+        fade: bool,
+        c2r: &dyn ComplexToReal<f32>,
+        scratch: &mut Scratch,
+    ) {
+        let block_len = self.output.len();
+        // the inverse FFT is not normalised
+        let scale = 1.0 / (2 * block_len) as f32;
+
+        if fade {
+            convolve(&self.prev_spectra, fdl, newest, c2r, scratch);
+            for ((output, overlap), &first) in self
+                .output
+                .iter_mut()
+                .zip(&self.prev_overlap)
+                .zip(&scratch.time)
+            {
+                *output = first * scale + overlap;
+            }
+        }
+
+        convolve(&self.spectra, fdl, newest, c2r, scratch);
+        let (first, second) = scratch.time.split_at(block_len);
+        for (n, (((output, overlap), &first), &second)) in self
+            .output
+            .iter_mut()
+            .zip(self.overlap.iter_mut())
+            .zip(first)
+            .zip(second)
+            .enumerate()
+        {
+            let new = first * scale + *overlap;
+            *output = if fade {
+                let gain = (n + 1) as f32 / block_len as f32;
+                *output + (new - *output) * gain
+            } else {
+                new
+            };
+            *overlap = second * scale;
+        }
+    }
+
+    /// Replace `overlap` with the tail the current IR would have left behind had it rendered the
+    /// last block, so the new IR starts out as if it had always been in use.
+    fn restart_overlap(
+        &mut self,
+        fdl: &[Complex32],
+        newest: usize,
+        c2r: &dyn ComplexToReal<f32>,
+        scratch: &mut Scratch,
+    ) {
+        let block_len = self.overlap.len();
+        let scale = 1.0 / (2 * block_len) as f32;
+
+        convolve(&self.spectra, fdl, newest, c2r, scratch);
+        for (overlap, &second) in self.overlap.iter_mut().zip(&scratch.time[block_len..]) {
+            *overlap = second * scale;
+        }
+    }
+}
+
+/// Convolve the delay line with the IR partition `spectra` into `scratch.time`, unnormalised.
+fn convolve(
+    spectra: &[Complex32],
+    fdl: &[Complex32],
+    newest: usize,
+    c2r: &dyn ComplexToReal<f32>,
+    scratch: &mut Scratch,
+) {
+    let bins = scratch.sum.len();
+    let block_len = bins - 1;
+    let partitions = fdl.len() / bins;
+
+    // partition p of the IR meets the input block from p blocks ago
+    scratch.sum.fill(Complex32::default());
+    for (partition, spectrum) in spectra.chunks_exact(bins).enumerate() {
+        let slot = (newest + partitions - partition) % partitions;
+        let block = &fdl[slot * bins..(slot + 1) * bins];
+        for ((sum, x), h) in scratch.sum.iter_mut().zip(block).zip(spectrum) {
+            *sum += x * h;
+        }
+    }
+    // the spectrum of a real signal, only rounding could make these non-zero
+    scratch.sum[0].im = 0.0;
+    scratch.sum[block_len].im = 0.0;
+
+    c2r.process_with_scratch(&mut scratch.sum, &mut scratch.time, &mut scratch.fft)
+        .unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::prelude::*;
+
+    fn direct_convolution(input: &[f32], ir: &[f32]) -> Vec<f32> {
+        (0..input.len())
+            .map(|n| {
+                ir.iter()
+                    .enumerate()
+                    .take(n + 1)
+                    .map(|(k, h)| h * input[n - k])
+                    .sum()
+            })
+            .collect()
+    }
+
+    fn random(len: usize, rng: &mut StdRng) -> Vec<f32> {
+        (0..len).map(|_| rng.gen_range(-1.0..1.0)).collect()
+    }
+
+    fn filter(left: &[f32], right: &[f32]) -> Filter {
+        let mut filter = Filter::new(left.len());
+        filter.left.copy_from_slice(left);
+        filter.right.copy_from_slice(right);
+        filter
+    }
+
+    #[test]
+    fn test_matches_direct_convolution() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let input = random(2000, &mut rng);
+
+        for (filter_len, block_len) in [(1, 64), (64, 64), (100, 64), (128, 64), (200, 32), (33, 1)] {
+            let left = random(filter_len, &mut rng);
+            let right = random(filter_len, &mut rng);
+            let expected_left = direct_convolution(&input, &left);
+            let expected_right = direct_convolution(&input, &right);
+
+            // the result must not depend on how the input is split up
+            for host_block in [1, 17, 64, 300, 2000] {
+                let mut ola = OlaConvolver::new(filter_len, block_len);
+                ola.set_filter(&filter(&left, &right));
+
+                let mut out_left = vec![0.0; input.len()];
+                let mut out_right = vec![0.0; input.len()];
+                for ((input, left), right) in input
+                    .chunks(host_block)
+                    .zip(out_left.chunks_mut(host_block))
+                    .zip(out_right.chunks_mut(host_block))
+                {
+                    ola.process_block(input, left, right);
+                }
+
+                let latency = ola.latency_samples() as usize;
+                assert_eq!(latency, block_len);
+                assert!(out_left[..latency].iter().all(|s| *s == 0.0));
+                for n in latency..input.len() {
+                    assert!(
+                        (out_left[n] - expected_left[n - latency]).abs() < 1e-4,
+                        "left, filter {filter_len}, block {host_block}, sample {n}"
+                    );
+                    assert!((out_right[n] - expected_right[n - latency]).abs() < 1e-4);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_filter_crossfade() {
+        let mut rng = StdRng::seed_from_u64(6);
+        let input = random(1024, &mut rng);
+        let first = random(100, &mut rng);
+        let second = random(100, &mut rng);
+        let old = direct_convolution(&input, &first);
+        let new = direct_convolution(&input, &second);
+
+        // the output block after the change goes from the old filter's output to the new one's
+        let mut ola = OlaConvolver::new(100, 64);
+        let mut left = vec![0.0; 1024];
+        let mut right = vec![0.0; 1024];
+        ola.set_filter(&filter(&first, &first));
+        ola.process_block(&input[..300], &mut left[..300], &mut right[..300]);
+        ola.set_filter(&filter(&second, &second));
+        ola.process_block(&input[300..], &mut left[300..], &mut right[300..]);
+
+        // the block rendered once the input reaches 320 is the first to use the new filter
+        let fade_start = 320;
+        for n in 64..input.len() {
+            let gain = ((n + 1).saturating_sub(fade_start) as f32 / 64.0).min(1.0);
+            let expected = old[n - 64] + (new[n - 64] - old[n - 64]) * gain;
+            assert!((left[n] - expected).abs() < 1e-4, "sample {n}");
+        }
+    }
+
+    #[test]
+    fn test_filter_change_and_reset() {
+        let mut rng = StdRng::seed_from_u64(4);
+        let input = random(1024, &mut rng);
+        let first = random(128, &mut rng);
+        let second = random(128, &mut rng);
+
+        let mut ola = OlaConvolver::new(128, 64);
+        let mut left = vec![0.0; 1024];
+        let mut right = vec![0.0; 1024];
+        ola.set_filter(&filter(&first, &first));
+        ola.process_block(&input, &mut left, &mut right);
+
+        // once the old input has left the delay line the output only depends on the new filter
+        ola.set_filter(&filter(&second, &second));
+        ola.process_block(&input, &mut left, &mut right);
+        let expected = direct_convolution(&[&input[..], &input[..]].concat(), &second);
+        for n in 256..1024 {
+            assert!((left[n] - expected[1024 + n - 64]).abs() < 1e-4);
+        }
+
+        ola.reset();
+        ola.process_block(&[0.0; 1024], &mut left, &mut right);
+        assert!(left.iter().chain(&right).all(|s| *s == 0.0));
+    }
+}
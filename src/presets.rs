@@ -0,0 +1,390 @@
+// Named presets: every parameter plus the files the plugin loads (SOFA dataset, headphone EQ and
+// motion path), stored as JSON. Factory presets are compiled in, user presets live in
+// `user_dir()`, and any preset can be exported to or imported from a file elsewhere.
+//
+// The parameters are kept in nih_plug's own state format, so applying a preset is a
+// `GuiContext::set_state`, which also reloads the files. Files are stored by path together with a
+// hash of their contents to notice when a preset points at a file that has since changed.
+
+use nih_plug::wrapper::state::{ParamValue, PluginState};
+use serde::{Deserialize, Serialize};
+
+use crate::migration::{self, VERSION_FIELD};
+use crate::spatializer_efx::Error;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// The persistent fields holding file paths, see `SpatializerParams`
+pub const FILE_FIELDS: [&str; 3] = ["sofa-path", "headphone-eq-path", "motion-path"];
+
+pub const EXTENSION: &str = "json";
+
+const FACTORY: [&str; 4] = [
+    include_str!("../presets/front.json"),
+    include_str!("../presets/hard_left.json"),
+    include_str!("../presets/overhead_circle.json"),
+    include_str!("../presets/fly_by.json"),
+];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Preset {
+    pub name: String,
+    /// The parameter layout `params` is in, see [`migration`]
+    #[serde(default = "first_version")]
+    pub version: u32,
+    /// Plain parameter values by parameter ID, as nih_plug stores them
+    pub params: BTreeMap<String, ParamValue>,
+    /// Files by the persistent field that holds their path. A field that is missing here is left
+    /// as it is when applying the preset, use a `path` of `None` to clear it.
+    #[serde(default)]
+    pub files: BTreeMap<String, FileRef>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileRef {
+    pub path: Option<String>,
+    /// [`content_hash`] of the file when the preset was saved, `None` if it could not be read
+    #[serde(default)]
+    pub hash: Option<String>,
+}
+
+/// Why a file a preset refers to may not be the one it was saved with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileProblem {
+    Missing { field: String, path: String },
+    Changed { field: String, path: String },
+}
+
+impl Preset {
+    /// Capture the current plugin state, e.g. from `GuiContext::get_state`. Fields that are not
+    /// files, like the editor size, are not part of a preset.
+    pub fn from_state(name: &str, state: &PluginState) -> Self {
+        let files = FILE_FIELDS
+            .iter()
+            .filter_map(|&field| {
+                let path = field_path(state.fields.get(field)?);
+                let hash = path.as_ref().and_then(|path| content_hash(path).ok());
+                Some((field.to_owned(), FileRef { path, hash }))
+            })
+            .collect();
+
+        Preset {
+            name: name.to_owned(),
+            version: migration::state_version(state),
+            params: state.params.clone(),
+            files,
+        }
+    }
+
+    /// `current` with this preset's parameters and files, for `GuiContext::set_state`. Presets
+    /// saved by older versions are migrated first. Parameters the preset doesn't know about, e.g.
+    /// ones added after it was saved, keep their value.
+    pub fn apply(&self, mut current: PluginState) -> PluginState {
+        for (id, value) in &self.params {
+            current.params.insert(id.clone(), value.clone());
+        }
+        for (field, file) in &self.files {
+            // persistent fields are stored as JSON themselves
+            let value = serde_json::to_string(&file.path).unwrap_or_else(|_| String::from("null"));
+            current.fields.insert(field.clone(), value);
+        }
+        current
+            .fields
+            .insert(VERSION_FIELD.to_owned(), self.version.to_string());
+        migration::migrate(&mut current);
+
+        current
+    }
+
+    /// Files that are gone or whose contents differ from when the preset was saved. Files saved
+    /// without a hash are only checked for existence.
+    pub fn check_files(&self) -> Vec<FileProblem> {
+        let mut problems = Vec::new();
+        for (field, file) in &self.files {
+            let Some(path) = &file.path else {
+                continue;
+            };
+
+            match content_hash(path) {
+                Err(_) => problems.push(FileProblem::Missing {
+                    field: field.clone(),
+                    path: path.clone(),
+                }),
+                Ok(hash) if file.hash.as_ref().is_some_and(|saved| *saved != hash) => problems
+                    .push(FileProblem::Changed {
+                        field: field.clone(),
+                        path: path.clone(),
+                    }),
+                Ok(_) => {}
+            }
+        }
+
+        problems
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        serde_json::from_str(text).map_err(|err| err.to_string())
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("presets always serialize")
+    }
+
+    /// Read a preset from any file.
+    pub fn import<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        fs::read_to_string(path)
+            .map_err(|err| err.to_string())
+            .and_then(|text| Self::parse(&text))
+            .map_err(|reason| Error::Preset {
+                path: path.display().to_string(),
+                reason,
+            })
+    }
+
+    /// Write the preset to any file.
+    pub fn export<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let path = path.as_ref();
+        fs::write(path, self.to_json()).map_err(|err| Error::Write {
+            path: path.display().to_string(),
+            reason: err.to_string(),
+        })
+    }
+
+    /// The file name the preset gets in the user preset directory.
+    pub fn file_name(&self) -> String {
+        let stem: String = self
+            .name
+            .chars()
+            .map(|c| {
+                if c.is_alphanumeric() || c == '-' || c == ' ' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        let stem = stem.trim();
+
+        format!(
+            "{}.{EXTENSION}",
+            if stem.is_empty() { "preset" } else { stem }
+        )
+    }
+}
+
+impl std::fmt::Display for FileProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FileProblem::Missing { field, path } => write!(f, "{field}: {path} not found"),
+            FileProblem::Changed { field, path } => {
+                write!(f, "{field}: {path} changed since the preset was saved")
+            }
+        }
+    }
+}
+
+/// Presets from before the version was recorded
+fn first_version() -> u32 {
+    1
+}
+
+/// The path in a serialized `RwLock<Option<String>>` field.
+fn field_path(value: &str) -> Option<String> {
+    serde_json::from_str::<Option<String>>(value).ok().flatten()
+}
+
+/// 64 bit FNV-1a of the file's contents as hex. Stable across builds and platforms, which the
+/// standard library's hashers are not.
+pub fn content_hash<P: AsRef<Path>>(path: P) -> std::io::Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let mut chunk = [0u8; 8192];
+    loop {
+        let len = file.read(&mut chunk)?;
+        if len == 0 {
+            break;
+        }
+        for &byte in &chunk[..len] {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    Ok(format!("{hash:016x}"))
+}
+
+/// The presets shipped with the plugin.
+pub fn factory() -> Vec<Preset> {
+    FACTORY
+        .iter()
+        .map(|text| Preset::parse(text).expect("factory presets are valid"))
+        .collect()
+}
+
+/// `$ASE_PRESET_DIR`, or `ase-project/presets` in the platform's per-user data directory.
+pub fn user_dir() -> Option<PathBuf> {
+    if let Some(dir) = std::env::var_os("ASE_PRESET_DIR") {
+        return Some(PathBuf::from(dir));
+    }
+
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    let data = if cfg!(target_os = "macos") {
+        home.map(|home| home.join("Library/Application Support"))
+    } else if cfg!(windows) {
+        std::env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        std::env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .or_else(|| home.map(|home| home.join(".local/share")))
+    };
+
+    data.map(|data| data.join("ase-project").join("presets"))
+}
+
+/// All presets in `dir` sorted by name, with the files that could not be read. A missing
+/// directory just has no presets.
+pub fn load_user(dir: &Path) -> (Vec<Preset>, Vec<Error>) {
+    let mut presets = Vec::new();
+    let mut errors = Vec::new();
+    let Ok(entries) = fs::read_dir(dir) else {
+        return (presets, errors);
+    };
+
+    for path in entries.filter_map(|entry| entry.ok().map(|entry| entry.path())) {
+        if path.extension().is_some_and(|ext| ext == EXTENSION) {
+            match Preset::import(&path) {
+                Ok(preset) => presets.push(preset),
+                Err(err) => errors.push(err),
+            }
+        }
+    }
+    presets.sort_by(|a, b| a.name.cmp(&b.name));
+
+    (presets, errors)
+}
+
+/// Save a preset to `dir`, replacing one with the same name.
+pub fn save_user(dir: &Path, preset: &Preset) -> Result<PathBuf, Error> {
+    fs::create_dir_all(dir).map_err(|err| Error::Write {
+        path: dir.display().to_string(),
+        reason: err.to_string(),
+    })?;
+    let path = dir.join(preset.file_name());
+    preset.export(&path)?;
+
+    Ok(path)
+}
+
+/// Copy a preset file into `dir`, under its own name.
+pub fn import_user<P: AsRef<Path>>(dir: &Path, path: P) -> Result<Preset, Error> {
+    let preset = Preset::import(path)?;
+    save_user(dir, &preset)?;
+
+    Ok(preset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ase-presets-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_factory_presets() {
+        let presets = factory();
+        assert_eq!(presets.len(), FACTORY.len());
+        for preset in &presets {
+            assert_eq!(preset.version, migration::STATE_VERSION, "{}", preset.name);
+            assert!(preset.params.contains_key("Azimuth"), "{}", preset.name);
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let dir = temp_dir("round-trip");
+        let sofa = dir.join("dataset.sofa");
+        fs::write(&sofa, b"not really a dataset").unwrap();
+        let sofa = sofa.display().to_string();
+
+        let mut state = PluginState {
+            version: String::from("0.1.0"),
+            params: BTreeMap::from([
+                (String::from("gain"), ParamValue::F32(0.5)),
+                (String::from("PinnaShift"), ParamValue::Bool(true)),
+            ]),
+            fields: BTreeMap::from([
+                (
+                    String::from("sofa-path"),
+                    serde_json::to_string(&Some(&sofa)).unwrap(),
+                ),
+                (String::from("motion-path"), String::from("null")),
+                (
+                    String::from(VERSION_FIELD),
+                    migration::STATE_VERSION.to_string(),
+                ),
+                (
+                    String::from("editor-state"),
+                    String::from("{\"size\":[660,660]}"),
+                ),
+            ]),
+        };
+        let preset = Preset::from_state("Test: 1", &state);
+        assert_eq!(
+            preset.files["sofa-path"].path.as_deref(),
+            Some(sofa.as_str())
+        );
+        assert!(preset.files["sofa-path"].hash.is_some());
+        assert_eq!(preset.files["motion-path"].path, None);
+        assert!(!preset.files.contains_key("editor-state"));
+        assert!(preset.check_files().is_empty());
+
+        let path = save_user(&dir, &preset).unwrap();
+        assert_eq!(path.file_name().unwrap(), "Test_ 1.json");
+        let (loaded, errors) = load_user(&dir);
+        assert!(errors.is_empty());
+        assert_eq!(loaded, vec![preset.clone()]);
+
+        // applying restores the values and keeps the editor size
+        state
+            .params
+            .insert(String::from("gain"), ParamValue::F32(1.0));
+        state
+            .fields
+            .insert(String::from("sofa-path"), String::from("null"));
+        let applied = preset.apply(state);
+        assert_eq!(applied.params["gain"], ParamValue::F32(0.5));
+        assert_eq!(field_path(&applied.fields["sofa-path"]), Some(sofa.clone()));
+        assert_eq!(applied.fields["editor-state"], "{\"size\":[660,660]}");
+
+        // presets from before the position parameters changed are migrated
+        let old =
+            Preset::parse(r#"{"name": "Old", "params": {"FrontBack": 0.0, "LeftRight": -180.0}}"#)
+                .unwrap();
+        assert_eq!(old.version, 1);
+        let applied = old.apply(applied);
+        assert!(!applied.params.contains_key("LeftRight"));
+        assert!(matches!(applied.params["Azimuth"], ParamValue::F32(azimuth) if azimuth > 89.0));
+
+        fs::write(&sofa, b"a different dataset").unwrap();
+        assert!(matches!(
+            preset.check_files()[..],
+            [FileProblem::Changed { .. }]
+        ));
+        fs::remove_file(&sofa).unwrap();
+        assert!(matches!(
+            preset.check_files()[..],
+            [FileProblem::Missing { .. }]
+        ));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
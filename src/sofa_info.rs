@@ -0,0 +1,278 @@
+// Reads the metadata of a SOFA file directly through netCDF, libmysofa (and so the sofar crate)
+// only hands out filters and keeps everything else about the dataset to itself
+
+use sofar::reader::{OpenOptions, Sofar};
+
+use crate::spatializer_efx::Error;
+
+use std::fmt::Write;
+use std::path::Path;
+
+/// The `SOFAConventions` we can render binaurally. SingleRoomSRIR is usually measured with a
+/// microphone array, those datasets are turned away by the receiver count check instead.
+pub const SUPPORTED_CONVENTIONS: &[&str] =
+    &["SimpleFreeFieldHRIR", "SingleRoomDRIR", "SingleRoomSRIR"];
+
+/// How `SourcePosition` is stored in the file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CoordinateType {
+    /// Azimuth in degrees, elevation in degrees and distance in metres
+    Spherical,
+    /// x (front), y (left), z (up) in metres
+    Cartesian,
+    /// Anything else found in the `Type` attribute
+    Other(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct SofaInfo {
+    /// Global `Conventions` attribute, should always be "SOFA"
+    pub conventions: String,
+    /// Global `SOFAConventions` attribute, e.g. "SimpleFreeFieldHRIR"
+    pub sofa_conventions: String,
+    pub sofa_conventions_version: String,
+    /// Global `DataType` attribute, e.g. "FIR"
+    pub data_type: String,
+    /// Global `Title` attribute, empty if the file has none
+    pub title: String,
+    pub sample_rate: f64,
+    /// Number of taps per impulse response (dimension N)
+    pub filter_len: usize,
+    /// Number of measurements (dimension M)
+    pub measurements: usize,
+    /// Number of receivers (dimension R), 2 for binaural datasets
+    pub receivers: usize,
+    pub coordinate_type: CoordinateType,
+    /// Source positions as stored in the file, see [`CoordinateType`]
+    pub source_positions: Vec<[f64; 3]>,
+}
+
+impl SofaInfo {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, netcdf::Error> {
+        let file = netcdf::open(path)?;
+
+        let dim = |name: &str| {
+            file.dimension_len(name)
+                .ok_or_else(|| netcdf::Error::NotFound(format!("dimension {name}")))
+        };
+        let measurements = dim("M")?;
+        let receivers = dim("R")?;
+        let filter_len = dim("N")?;
+
+        let sample_rate = file
+            .variable("Data.SamplingRate")
+            .ok_or_else(|| netcdf::Error::NotFound("variable Data.SamplingRate".into()))?
+            .get_value::<f64, _>(..)?;
+
+        let source = file
+            .variable("SourcePosition")
+            .ok_or_else(|| netcdf::Error::NotFound("variable SourcePosition".into()))?;
+        let coordinate_type = match string_attribute(source.attribute_value("Type"))? {
+            Some(t) if t == "cartesian" => CoordinateType::Cartesian,
+            Some(t) if t != "spherical" => CoordinateType::Other(t),
+            // Spherical is the SOFA default and what every HRIR dataset we have seen so far uses
+            _ => CoordinateType::Spherical,
+        };
+
+        // SourcePosition is either (M, C) or (I, C) when all measurements share one position
+        let values = source.get_values::<f64, _>(..)?;
+        let source_positions = values
+            .chunks_exact(3)
+            .map(|c| [c[0], c[1], c[2]])
+            .collect();
+
+        let global = |name: &str| -> Result<String, netcdf::Error> {
+            Ok(string_attribute(file.attribute(name).map(|a| a.value()))?.unwrap_or_default())
+        };
+
+        Ok(SofaInfo {
+            conventions: global("Conventions")?,
+            sofa_conventions: global("SOFAConventions")?,
+            sofa_conventions_version: global("SOFAConventionsVersion")?,
+            data_type: global("DataType")?,
+            title: global("Title")?,
+            sample_rate,
+            filter_len,
+            measurements,
+            receivers,
+            coordinate_type,
+            source_positions,
+        })
+    }
+
+    /// The source positions as (azimuth degrees, elevation degrees, distance metres), regardless of
+    /// how they're stored in the file.
+    pub fn spherical_positions(&self) -> Vec<[f64; 3]> {
+        match self.coordinate_type {
+            CoordinateType::Spherical | CoordinateType::Other(_) => self.source_positions.clone(),
+            CoordinateType::Cartesian => self
+                .source_positions
+                .iter()
+                .map(|&[x, y, z]| {
+                    let horizontal = x.hypot(y);
+                    [
+                        y.atan2(x).to_degrees(),
+                        z.atan2(horizontal).to_degrees(),
+                        horizontal.hypot(z),
+                    ]
+                })
+                .collect(),
+        }
+    }
+
+    /// Smallest and largest source distance in metres.
+    pub fn distance_range(&self) -> (f64, f64) {
+        self.spherical_positions()
+            .iter()
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), p| {
+                (min.min(p[2]), max.max(p[2]))
+            })
+    }
+
+    /// Plot which azimuth/elevation cells contain at least one measurement. Azimuth runs from -180
+    /// on the left to 180 degrees on the right, elevation from 90 at the top to -90 at the bottom.
+    pub fn coverage_map(&self, cols: usize, rows: usize) -> String {
+        let mut grid = vec![vec![false; cols]; rows];
+        for [az, el, _] in self.spherical_positions() {
+            // wrap azimuth to [-180, 180)
+            let az = (az + 180.0).rem_euclid(360.0);
+            let col = ((az / 360.0 * cols as f64) as usize).min(cols - 1);
+            let row = (((90.0 - el) / 180.0 * rows as f64) as usize).min(rows - 1);
+            grid[row][col] = true;
+        }
+
+        let mut map = String::new();
+        for (row, cells) in grid.iter().enumerate() {
+            let el = 90.0 - (row as f64 + 0.5) * 180.0 / rows as f64;
+            let line: String = cells.iter().map(|&c| if c { '#' } else { '.' }).collect();
+            writeln!(map, "{el:>6.1} |{line}|").unwrap();
+        }
+        writeln!(map, "{:>6} +{}+", "", "-".repeat(cols)).unwrap();
+        writeln!(map, "{:>6}  -180{:^w$}180", "", "0", w = cols.saturating_sub(7)).unwrap();
+
+        map
+    }
+
+    /// Check that the dataset is something the plugin can render binaurally.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.conventions != "SOFA" {
+            return Err(Error::NotSofa(self.conventions.clone()));
+        }
+        if !SUPPORTED_CONVENTIONS.contains(&self.sofa_conventions.as_str()) {
+            return Err(Error::UnsupportedConvention(self.sofa_conventions.clone()));
+        }
+        if self.data_type != "FIR" {
+            return Err(Error::UnsupportedDataType(self.data_type.clone()));
+        }
+        if self.receivers != 2 {
+            return Err(Error::ReceiverCount(self.receivers));
+        }
+        if !self.sample_rate.is_finite() || self.sample_rate <= 0.0 {
+            return Err(Error::InvalidSampleRate(self.sample_rate));
+        }
+        if let CoordinateType::Other(name) = &self.coordinate_type {
+            return Err(Error::UnsupportedCoordinates(name.clone()));
+        }
+
+        Ok(())
+    }
+}
+
+/// Validate and open a SOFA file for rendering at `sample_rate`, libmysofa resamples the IRs if the
+/// dataset was measured at a different rate.
+pub fn open_sofa<P: AsRef<Path>>(path: P, sample_rate: f32) -> Result<Sofar, Error> {
+    let path = path.as_ref();
+    let info = SofaInfo::open(path).map_err(|err| Error::Open {
+        path: path.display().to_string(),
+        reason: err.to_string(),
+    })?;
+    info.validate()?;
+
+    OpenOptions::new()
+        .sample_rate(sample_rate)
+        .open(path)
+        .map_err(|err| Error::Sofar(err.to_string()))
+}
+
+fn string_attribute(
+    value: Option<Result<netcdf::AttributeValue, netcdf::Error>>,
+) -> Result<Option<String>, netcdf::Error> {
+    match value.transpose()? {
+        Some(netcdf::AttributeValue::Str(s)) => Ok(Some(s)),
+        Some(netcdf::AttributeValue::Strs(s)) => Ok(Some(s.join(" "))),
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    #[test]
+    fn test_cartesian_coverage() {
+        let info = SofaInfo {
+            conventions: "SOFA".into(),
+            sofa_conventions: "SimpleFreeFieldHRIR".into(),
+            sofa_conventions_version: "1.0".into(),
+            data_type: "FIR".into(),
+            title: String::new(),
+            sample_rate: 48000.0,
+            filter_len: 128,
+            measurements: 2,
+            receivers: 2,
+            coordinate_type: CoordinateType::Cartesian,
+            // straight ahead at 1 m and straight up at 2 m, both land in the centre column
+            source_positions: vec![[1.0, 0.0, 0.0], [0.0, 0.0, 2.0]],
+        };
+
+        let spherical = info.spherical_positions();
+        assert_approx_eq!(spherical[0][0], 0.0);
+        assert_approx_eq!(spherical[1][1], 90.0);
+        assert_eq!(info.distance_range(), (1.0, 2.0));
+
+        let map = info.coverage_map(4, 2);
+        let rows: Vec<&str> = map.lines().collect();
+        assert!(rows[0].ends_with("|..#.|"));
+        assert!(rows[1].ends_with("|..#.|"));
+
+        assert!(info.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_errors() {
+        let mut info = SofaInfo {
+            conventions: "SOFA".into(),
+            sofa_conventions: "SimpleHeadphoneIR".into(),
+            sofa_conventions_version: "1.0".into(),
+            data_type: "FIR".into(),
+            title: String::new(),
+            sample_rate: 48000.0,
+            filter_len: 128,
+            measurements: 1,
+            receivers: 32,
+            coordinate_type: CoordinateType::Spherical,
+            source_positions: vec![[0.0, 0.0, 1.0]],
+        };
+        assert!(matches!(info.validate(), Err(Error::UnsupportedConvention(_))));
+
+        // a microphone array room response, the convention is fine but it isn't binaural
+        info.sofa_conventions = "SingleRoomSRIR".into();
+        assert!(matches!(info.validate(), Err(Error::ReceiverCount(32))));
+
+        info.sofa_conventions = "SimpleFreeFieldHRIR".into();
+        info.conventions = "netCDF".into();
+        assert!(matches!(info.validate(), Err(Error::NotSofa(_))));
+
+        info.conventions = "SOFA".into();
+        assert!(matches!(info.validate(), Err(Error::ReceiverCount(32))));
+
+        info.receivers = 2;
+        info.sample_rate = 0.0;
+        assert!(matches!(info.validate(), Err(Error::InvalidSampleRate(_))));
+
+        info.sample_rate = 44100.0;
+        info.coordinate_type = CoordinateType::Other("cylindrical".into());
+        assert!(matches!(info.validate(), Err(Error::UnsupportedCoordinates(_))));
+    }
+}
@@ -0,0 +1,240 @@
+// Exports (modified) HRIR sets as SOFA files in the SimpleFreeFieldHRIR 1.0 convention, so
+// resampled, equalised or personalised datasets can be loaded back into the plugin
+
+use crate::sofa_info::SofaInfo;
+use crate::spatializer_efx::Error;
+
+use std::path::Path;
+
+/// A complete set of HRIRs, one left/right pair per source position.
+#[derive(Debug, Clone)]
+pub struct HrirSet {
+    pub sample_rate: f64,
+    pub filter_len: usize,
+    /// (azimuth degrees, elevation degrees, distance metres) per measurement
+    pub source_positions: Vec<[f64; 3]>,
+    /// Impulse responses in the SOFA (M, R, N) layout: measurement, ear (left first), tap
+    pub data_ir: Vec<f64>,
+    /// Written to the `Title` attribute
+    pub title: String,
+}
+
+impl HrirSet {
+    pub fn new(sample_rate: f64, filter_len: usize) -> Self {
+        HrirSet {
+            sample_rate,
+            filter_len,
+            source_positions: Vec::new(),
+            data_ir: Vec::new(),
+            title: String::from("ASE Spatializer HRIR set"),
+        }
+    }
+
+    /// Add a measurement at `position` (azimuth degrees, elevation degrees, distance metres).
+    pub fn push(&mut self, position: [f64; 3], left: &[f32], right: &[f32]) {
+        assert_eq!(left.len(), self.filter_len);
+        assert_eq!(right.len(), self.filter_len);
+
+        self.source_positions.push(position);
+        self.data_ir.extend(left.iter().map(|&s| s as f64));
+        self.data_ir.extend(right.iter().map(|&s| s as f64));
+    }
+
+    pub fn measurements(&self) -> usize {
+        self.source_positions.len()
+    }
+
+    /// The IR of `measurement` for `receiver` 0 (left) or 1 (right).
+    pub fn ir(&self, measurement: usize, receiver: usize) -> &[f64] {
+        let start = (measurement * 2 + receiver) * self.filter_len;
+        &self.data_ir[start..start + self.filter_len]
+    }
+
+    pub fn ir_mut(&mut self, measurement: usize, receiver: usize) -> &mut [f64] {
+        let start = (measurement * 2 + receiver) * self.filter_len;
+        &mut self.data_ir[start..start + self.filter_len]
+    }
+
+    /// Read a complete dataset, cartesian source positions are converted to spherical ones.
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let open_error = |err: netcdf::Error| Error::Open {
+            path: path.display().to_string(),
+            reason: err.to_string(),
+        };
+
+        let info = SofaInfo::open(path).map_err(open_error)?;
+        info.validate()?;
+
+        let file = netcdf::open(path).map_err(open_error)?;
+        let data_ir = file
+            .variable("Data.IR")
+            .ok_or_else(|| open_error(netcdf::Error::NotFound("variable Data.IR".into())))?
+            .get_values::<f64, _>(..)
+            .map_err(open_error)?;
+
+        Ok(HrirSet {
+            sample_rate: info.sample_rate,
+            filter_len: info.filter_len,
+            source_positions: info.spherical_positions(),
+            data_ir,
+            title: info.title,
+        })
+    }
+
+    /// Write the set as a SimpleFreeFieldHRIR file, an existing file at `path` is overwritten.
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let path = path.as_ref();
+        self.write_netcdf(path).map_err(|err| Error::Write {
+            path: path.display().to_string(),
+            reason: err.to_string(),
+        })
+    }
+
+    fn write_netcdf(&self, path: &Path) -> Result<(), netcdf::Error> {
+        let m = self.measurements();
+        let mut file = netcdf::create(path)?;
+
+        // Mandatory global attributes of SimpleFreeFieldHRIR 1.0
+        file.add_attribute("Conventions", "SOFA")?;
+        file.add_attribute("Version", "2.1")?;
+        file.add_attribute("SOFAConventions", "SimpleFreeFieldHRIR")?;
+        file.add_attribute("SOFAConventionsVersion", "1.0")?;
+        file.add_attribute("APIName", env!("CARGO_PKG_NAME"))?;
+        file.add_attribute("APIVersion", env!("CARGO_PKG_VERSION"))?;
+        file.add_attribute("AuthorContact", "")?;
+        file.add_attribute("Organization", "")?;
+        file.add_attribute("License", "No license provided, ask the author for permission")?;
+        file.add_attribute("DataType", "FIR")?;
+        file.add_attribute("RoomType", "free field")?;
+        file.add_attribute("Title", self.title.as_str())?;
+        file.add_attribute("DatabaseName", "")?;
+        file.add_attribute("ListenerShortName", "")?;
+        file.add_attribute("DateCreated", "")?;
+        file.add_attribute("DateModified", "")?;
+
+        file.add_dimension("I", 1)?;
+        file.add_dimension("C", 3)?;
+        file.add_dimension("R", 2)?;
+        file.add_dimension("E", 1)?;
+        file.add_dimension("N", self.filter_len)?;
+        file.add_dimension("M", m)?;
+
+        // Listener at the origin looking along +x with +z up, ears 9 cm to either side
+        let mut var = file.add_variable::<f64>("ListenerPosition", &["I", "C"])?;
+        var.put_attribute("Type", "cartesian")?;
+        var.put_attribute("Units", "metre")?;
+        var.put_values(&[0.0, 0.0, 0.0], ..)?;
+
+        let mut var = file.add_variable::<f64>("ListenerUp", &["I", "C"])?;
+        var.put_attribute("Type", "cartesian")?;
+        var.put_attribute("Units", "metre")?;
+        var.put_values(&[0.0, 0.0, 1.0], ..)?;
+
+        let mut var = file.add_variable::<f64>("ListenerView", &["I", "C"])?;
+        var.put_attribute("Type", "cartesian")?;
+        var.put_attribute("Units", "metre")?;
+        var.put_values(&[1.0, 0.0, 0.0], ..)?;
+
+        let mut var = file.add_variable::<f64>("ReceiverPosition", &["R", "C", "I"])?;
+        var.put_attribute("Type", "cartesian")?;
+        var.put_attribute("Units", "metre")?;
+        var.put_values(&[0.0, 0.09, 0.0, 0.0, -0.09, 0.0], ..)?;
+
+        let mut var = file.add_variable::<f64>("EmitterPosition", &["E", "C", "I"])?;
+        var.put_attribute("Type", "cartesian")?;
+        var.put_attribute("Units", "metre")?;
+        var.put_values(&[0.0, 0.0, 0.0], ..)?;
+
+        let positions: Vec<f64> = self.source_positions.iter().flatten().copied().collect();
+        let mut var = file.add_variable::<f64>("SourcePosition", &["M", "C"])?;
+        var.put_attribute("Type", "spherical")?;
+        var.put_attribute("Units", "degree, degree, metre")?;
+        var.put_values(&positions, ..)?;
+
+        let mut var = file.add_variable::<f64>("Data.IR", &["M", "R", "N"])?;
+        var.put_values(&self.data_ir, ..)?;
+
+        let mut var = file.add_variable::<f64>("Data.SamplingRate", &["I"])?;
+        var.put_attribute("Units", "hertz")?;
+        var.put_values(&[self.sample_rate], ..)?;
+
+        let mut var = file.add_variable::<f64>("Data.Delay", &["I", "R"])?;
+        var.put_values(&[0.0, 0.0], ..)?;
+
+        file.close()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::TempSofa;
+    use assert_approx_eq::assert_approx_eq;
+    use sofar::reader::{Filter, OpenOptions};
+
+    const FILTER_LEN: usize = 32;
+
+    /// Horizontal ring every 30 degrees plus two elevated rings, each IR is a unique pair of taps
+    fn test_set() -> HrirSet {
+        let mut set = HrirSet::new(48000.0, FILTER_LEN);
+        for el in [-30.0, 0.0, 30.0] {
+            for az in (0..360).step_by(30) {
+                let idx = set.measurements();
+                let mut left = [0.0; FILTER_LEN];
+                let mut right = [0.0; FILTER_LEN];
+                left[idx % FILTER_LEN] = 0.5;
+                right[(idx + 3) % FILTER_LEN] = 0.25;
+                set.push([az as f64, el, 1.5], &left, &right);
+            }
+        }
+
+        set
+    }
+
+    #[test]
+    fn test_roundtrip_netcdf() {
+        let mut set = test_set();
+        set.title = String::from("Round trip");
+        let file = TempSofa::new("roundtrip-netcdf", &set).unwrap();
+        let path = file.path();
+
+        let info = SofaInfo::open(path).unwrap();
+        assert!(info.validate().is_ok());
+        assert_eq!(info.measurements, set.measurements());
+
+        let read = HrirSet::read(path).unwrap();
+        assert_eq!(read.filter_len, FILTER_LEN);
+        assert_approx_eq!(read.sample_rate, 48000.0);
+        assert_eq!(read.source_positions, set.source_positions);
+        assert_eq!(read.data_ir, set.data_ir);
+        assert_eq!(read.title, "Round trip");
+    }
+
+    #[test]
+    fn test_roundtrip_sofar() {
+        let set = test_set();
+        let file = TempSofa::new("roundtrip-sofar", &set).unwrap();
+
+        // the same reader the plugin uses, without loudness normalisation so the taps survive
+        let sofa = OpenOptions::new()
+            .sample_rate(48000.0)
+            .normalized(false)
+            .open(file.path())
+            .unwrap();
+        assert_eq!(sofa.filter_len(), FILTER_LEN);
+
+        let mut filter = Filter::new(FILTER_LEN);
+        for (m, &[az, el, _]) in set.source_positions.iter().enumerate() {
+            let (az, el) = (az.to_radians() as f32, el.to_radians() as f32);
+            sofa.filter_nointerp(az.cos() * el.cos(), az.sin() * el.cos(), el.sin(), &mut filter);
+
+            for (a, b) in filter.left.iter().zip(set.ir(m, 0)) {
+                assert_approx_eq!(*a, *b as f32, 1e-5);
+            }
+            for (a, b) in filter.right.iter().zip(set.ir(m, 1)) {
+                assert_approx_eq!(*a, *b as f32, 1e-5);
+            }
+        }
+    }
+}
@@ -1,169 +1,811 @@
-// Not used in lib.rs, Only for testing the Sofar crates filter extraction and HRTF convolutions
+// The DSP core of the spatializer, shared by the nih_plug `Spatializer` and the offline tools.
+// It owns the HRTF backend and all of its state so audio can be fed through it block by block.
 
-use sofar::reader::{Filter, OpenOptions, Sofar};
-use sofar::render::Renderer;
-use hound::{WavReader, WavWriter};
+use nih_plug::prelude::Enum;
+use sofar::reader::{Filter, Sofar};
 
-use nih_plug::params::enums::Enum;
+use crate::doppler::Doppler;
+use crate::head_model;
+use crate::headphone_eq::HeadphoneEq;
+use crate::hrtf_backend::{HrtfBackend, SofarBackend};
+use crate::trajectory::{NoteDivision, Trajectory, TransportState};
+
+use std::fmt;
+
+/// Partition length of the uniformly partitioned convolution in `sofar::render::Renderer` and
+/// [`OlaConvolver`](crate::ola::OlaConvolver).
+pub const PARTITION_LEN: usize = 64;
+/// While the source is moving along a trajectory its position is updated every this many samples.
+/// This control rate is the design rather than per-sample positions: the HRIR pair can't change
+/// faster than once per convolution partition anyway, the Doppler delay glides between the
+/// updates sample by sample, and the updates fall on fixed sample counts since the last reset
+/// whatever the host's block size.
+pub const CONTROL_LEN: usize = PARTITION_LEN;
+/// How far, in samples, the transport may be from where the trajectory expects it before it counts
+/// as a jump.
+const JUMP_TOLERANCE: f64 = CONTROL_LEN as f64 / 2.0;
 
-// #[derive(Debug, Clone)]
 pub struct SpatializerEfx {
-    x: f32,
-    y: f32,
-    z: f32,
-    sofa: Sofar,
+    // The source position is stored in spherical coordinates so the azimuth survives passing
+    // through the poles, see `set_param`
+    azimuth: f32,
+    elevation: f32,
+    distance: f32,
+    head_size: f32,
+    pinna_shift: bool,
+    /// Set when the position or head parameters changed and the filter has to be looked up again
+    filter_dirty: bool,
+    /// Automatic motion around the position above
+    trajectory: Trajectory,
+    /// The position the current filter was looked up for
+    rendered: (f32, f32, f32),
+    /// Incremented every time the filter is looked up
+    filter_generation: u64,
+    /// Samples processed since the start of the current control block
+    control_pos: usize,
+    /// Whether the trajectory is locked to a playing transport, see `sync_trajectory`
+    transport_locked: bool,
+    sample_rate: f32,
+
+    backend: Box<dyn HrtfBackend>,
+    doppler: Doppler,
+    hp_eq: Option<HeadphoneEq>,
+    hp_eq_bypass: bool,
+
+    // Scratch buffers so `process` never allocates, blocks larger than this are split up
+    mono: Vec<f32>,
+    left: Vec<f32>,
+    right: Vec<f32>,
 }
 
-#[derive(Debug, Clone, Copy)]
+/// How the HRIRs are convolved with the input. Both give the same result, the overlap-add engine
+/// one partition later.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Enum)]
+pub enum ConvolutionEngine {
+    /// `sofar::render::Renderer`
+    #[name = "sofar"]
+    Sofar,
+    /// [`OlaConvolver`](crate::ola::OlaConvolver)
+    #[name = "FFT Overlap-Add"]
+    OverlapAdd,
+}
+
+/// The largest distance from the listener that can be set, in metres.
+pub const MAX_DISTANCE: f32 = 20.0;
+/// The closest a source can get to the centre of the head, in metres. There is no IR at the origin,
+/// but any direction away from it will do.
+pub const MIN_DISTANCE: f32 = 0.001;
+
+/// The source position parameters. The cartesian and spherical parameters describe the same
+/// position, setting one updates the others.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CoordParam {
+    /// Front (+) to back (-) in metres
     Xcoord,
+    /// Left (+) to right (-) in metres
     Ycoord,
+    /// Up (+) to down (-) in metres
     Zcoord,
+    /// Degrees counterclockwise from the front when looking down, so 90 is left
+    Azimuth,
+    /// Degrees above (+) or below (-) the horizontal plane
+    Elevation,
+    /// Metres from the centre of the head
+    Distance,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    Metres,
+    Degrees,
+}
+
+impl Unit {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Unit::Metres => "m",
+            Unit::Degrees => "deg",
+        }
+    }
+}
+
+/// Everything a host or command line tool needs to know to present a [`CoordParam`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParamInfo {
+    pub param: CoordParam,
+    pub name: &'static str,
+    pub unit: Unit,
+    pub min: f32,
+    pub max: f32,
+    pub default: f32,
+}
+
+/// All parameters of the DSP core, in the order they should be presented.
+pub const PARAMS: [ParamInfo; 6] = [
+    ParamInfo {
+        param: CoordParam::Azimuth,
+        name: "Azimuth",
+        unit: Unit::Degrees,
+        // both -180..180 and 0..360 conventions are accepted and wrapped to -180..180
+        min: -180.0,
+        max: 360.0,
+        default: 0.0,
+    },
+    ParamInfo {
+        param: CoordParam::Elevation,
+        name: "Elevation",
+        unit: Unit::Degrees,
+        min: -90.0,
+        max: 90.0,
+        default: 0.0,
+    },
+    ParamInfo {
+        param: CoordParam::Distance,
+        name: "Distance",
+        unit: Unit::Metres,
+        min: MIN_DISTANCE,
+        max: MAX_DISTANCE,
+        default: 1.0,
+    },
+    ParamInfo {
+        param: CoordParam::Xcoord,
+        name: "X",
+        unit: Unit::Metres,
+        min: -MAX_DISTANCE,
+        max: MAX_DISTANCE,
+        default: 1.0,
+    },
+    ParamInfo {
+        param: CoordParam::Ycoord,
+        name: "Y",
+        unit: Unit::Metres,
+        min: -MAX_DISTANCE,
+        max: MAX_DISTANCE,
+        default: 0.0,
+    },
+    ParamInfo {
+        param: CoordParam::Zcoord,
+        name: "Z",
+        unit: Unit::Metres,
+        min: -MAX_DISTANCE,
+        max: MAX_DISTANCE,
+        default: 0.0,
+    },
+];
+
+impl CoordParam {
+    pub fn info(self) -> &'static ParamInfo {
+        PARAMS.iter().find(|info| info.param == self).unwrap()
+    }
+
+    /// Check that `value` is a number inside of the declared range.
+    pub fn validate(self, value: f32) -> Result<f32, Error> {
+        let info = self.info();
+        if value.is_finite() && value >= info.min && value <= info.max {
+            Ok(value)
+        } else {
+            Err(Error::InvalidValue { param: self, value })
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub enum Error {
-    InvalidValue { param: CoordParam, value: f32 }
+    InvalidValue { param: CoordParam, value: f32 },
+    /// The file is missing or is not a readable netCDF/SOFA file
+    Open { path: String, reason: String },
+    /// The global `Conventions` attribute is not "SOFA", so this is some other netCDF file
+    NotSofa(String),
+    /// `SOFAConventions` is not one we can render binaurally
+    UnsupportedConvention(String),
+    /// `DataType` is not FIR, e.g. a TF (frequency domain) dataset
+    UnsupportedDataType(String),
+    /// Binaural rendering needs exactly two receivers (ears)
+    ReceiverCount(usize),
+    /// `Data.SamplingRate` is missing, zero or not a finite number
+    InvalidSampleRate(f64),
+    /// `SourcePosition` uses something other than spherical or cartesian coordinates
+    UnsupportedCoordinates(String),
+    /// The dataset passed validation but libmysofa still refused it
+    Sofar(String),
+    /// An HRIR sphere was opened somewhere that renders on an audio thread
+    SphereNotRealtime(String),
+    /// Exporting a dataset failed
+    Write { path: String, reason: String },
+    /// A headphone EQ profile could not be read or parsed
+    EqProfile { path: String, reason: String },
+    /// An FIR headphone EQ profile was designed for a different sample rate
+    EqSampleRate { profile: u32, plugin: f32 },
+    /// A preset file could not be read or parsed
+    Preset { path: String, reason: String },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidValue { param, value } => {
+                let info = param.info();
+                write!(
+                    f,
+                    "invalid value {value} for {}, expected {} to {} {}",
+                    info.name,
+                    info.min,
+                    info.max,
+                    info.unit.symbol()
+                )
+            }
+            Error::Open { path, reason } => write!(f, "could not open {path}: {reason}"),
+            Error::NotSofa(conventions) => write!(
+                f,
+                "not a SOFA file, its Conventions attribute is '{conventions}' instead of 'SOFA'"
+            ),
+            Error::UnsupportedConvention(name) => write!(
+                f,
+                "unsupported SOFA convention '{name}', use a SimpleFreeFieldHRIR, SingleRoomDRIR or SingleRoomSRIR dataset"
+            ),
+            Error::UnsupportedDataType(name) => {
+                write!(f, "unsupported data type '{name}', only FIR datasets can be rendered")
+            }
+            Error::ReceiverCount(count) => write!(
+                f,
+                "dataset has {count} receivers, binaural rendering needs exactly 2 (left and right ear)"
+            ),
+            Error::InvalidSampleRate(rate) => write!(f, "invalid sample rate {rate} Hz"),
+            Error::UnsupportedCoordinates(name) => write!(
+                f,
+                "unsupported source coordinate type '{name}', expected spherical or cartesian"
+            ),
+            Error::Sofar(reason) => write!(f, "libmysofa could not load the dataset: {reason}"),
+            Error::SphereNotRealtime(path) => write!(
+                f,
+                "{path} is an HRIR sphere, those allocate while rendering and only work in ase-render, use a SOFA dataset"
+            ),
+            Error::Write { path, reason } => write!(f, "could not write {path}: {reason}"),
+            Error::EqProfile { path, reason } => {
+                write!(f, "could not load headphone EQ profile {path}: {reason}")
+            }
+            Error::EqSampleRate { profile, plugin } => write!(
+                f,
+                "the headphone EQ filter was designed for {profile} Hz but the plugin runs at {plugin} Hz"
+            ),
+            Error::Preset { path, reason } => write!(f, "could not load preset {path}: {reason}"),
+        }
+    }
 }
 
+impl std::error::Error for Error {}
+
 impl SpatializerEfx {
-    pub fn new(sofa: Sofar) -> Self {
+    /// Render a SOFA dataset with sofar, see [`SofarBackend`].
+    pub fn new(sofa: Sofar, sample_rate: f32, max_block_size: usize) -> Result<Self, Error> {
+        let backend = SofarBackend::new(sofa, sample_rate)?;
+
+        Ok(Self::with_backend(Box::new(backend), sample_rate, max_block_size))
+    }
+
+    /// Render with any HRTF backend, e.g. one returned by [`crate::hrtf_backend::open`].
+    pub fn with_backend(
+        backend: Box<dyn HrtfBackend>,
+        sample_rate: f32,
+        max_block_size: usize,
+    ) -> Self {
+        let max_block_size = max_block_size.max(1);
 
         SpatializerEfx {
-            x: 1.0,
-            y: 0.0,
-            z: 0.0,            
-            sofa,
+            azimuth: CoordParam::Azimuth.info().default,
+            elevation: CoordParam::Elevation.info().default,
+            distance: CoordParam::Distance.info().default,
+            head_size: head_model::REFERENCE_CIRCUMFERENCE,
+            pinna_shift: false,
+            filter_dirty: true,
+            trajectory: Trajectory::new(sample_rate),
+            rendered: (f32::NAN, f32::NAN, f32::NAN),
+            filter_generation: 0,
+            control_pos: 0,
+            transport_locked: false,
+            sample_rate,
+            backend,
+            doppler: Doppler::new(sample_rate),
+            hp_eq: None,
+            hp_eq_bypass: false,
+            mono: vec![0.0; max_block_size],
+            left: vec![0.0; max_block_size],
+            right: vec![0.0; max_block_size],
         }
-
     }
 
+    /// Render the average of the input channels binaurally and write the left and right ear back to
+    /// the first two channels. State is kept between calls, so consecutive blocks are continuous.
     pub fn process(&mut self, buffers: &mut [&mut [f32]]) {
-        let filt_len = self.sofa.filter_len();
-        let mut filter = Filter::new(filt_len);
-
-        // get filter at position
-        self.sofa.filter(self.x, self.y, self.z, &mut filter);
+        if buffers.len() < 2 {
+            return;
+        }
 
-        // sofa Renderer
-        let mut render = Renderer::builder(filt_len)
-            .with_sample_rate(48000.0)
-            .with_partition_len(64)
-            .build()
-            .unwrap();  
-        render.set_filter(&filter);
+        // a moving source gets a new filter every `CONTROL_LEN` samples, counted from the last
+        // reset so the motion doesn't depend on the host's block size
+        let moving = self.trajectory.is_moving();
 
-        // convert stereo to mono
         let num_samples = buffers[0].len();
-        let mut mono_audio = Vec::with_capacity(num_samples);
-        for i in 0..num_samples {
-            let sample = (buffers[0][i] + buffers[1][i]) / 2.0;
-            mono_audio.push(sample);
-        }         
+        let max_block_size = self.mono.len();
+        let mut start = 0;
+        while start < num_samples {
+            let mut end = (start + max_block_size).min(num_samples);
+            let position = if moving {
+                end = end.min(start + CONTROL_LEN - self.control_pos);
+                self.trajectory
+                    .position((self.azimuth, self.elevation, self.distance))
+            } else {
+                (self.azimuth, self.elevation, self.distance)
+            };
+            if self.filter_dirty || position != self.rendered {
+                self.update_filter(position);
+            }
+            let len = end - start;
+
+            // convert stereo to mono
+            let mono = &mut self.mono[..len];
+            for (i, sample) in mono.iter_mut().enumerate() {
+                *sample = (buffers[0][start + i] + buffers[1][start + i]) / 2.0;
+            }
+
+            // pitch shift from the radial velocity before it gets its direction
+            self.doppler.set_distance(position.2);
+            self.doppler.process(mono);
+
+            let left = &mut self.left[..len];
+            let right = &mut self.right[..len];
+            self.backend.process_block(mono, left, right);
+
+            // compensate for the headphones the binaural signal is played back on
+            if let Some(hp_eq) = &mut self.hp_eq {
+                if !self.hp_eq_bypass {
+                    hp_eq.process(left, right);
+                }
+            }
+
+            buffers[0][start..end].copy_from_slice(left);
+            buffers[1][start..end].copy_from_slice(right);
 
-        let mut left = vec![0.0; buffers[0].len()];
-        let mut right = vec![0.0; buffers[0].len()];
-        render.process_block(&mono_audio, &mut left, &mut right).unwrap();
-        dbg!(left.len());
+            // the trajectory only moves on at the control block boundaries, so a position looked up
+            // halfway through a control block is the same as at its start. A static source is
+            // not split into control blocks, motion switched on later starts a new one.
+            if moving {
+                self.control_pos += len;
+                if self.control_pos == CONTROL_LEN {
+                    self.trajectory.advance(CONTROL_LEN);
+                    self.control_pos = 0;
+                }
+            } else {
+                self.control_pos = 0;
+            }
+            start = end;
+        }
+    }
+
+    /// Clear the convolution and EQ state without reallocating anything, used when the host
+    /// restarts playback.
+    pub fn reset(&mut self) {
+        self.backend.reset();
+
+        if let Some(hp_eq) = &mut self.hp_eq {
+            hp_eq.reset();
+        }
+
+        self.doppler.reset();
+        self.trajectory.reset();
+        self.control_pos = 0;
+        self.transport_locked = false;
     }
 
+    /// Switch the convolution engine of backends that have more than one.
+    pub fn set_engine(&mut self, engine: ConvolutionEngine) {
+        self.backend.set_engine(engine);
+    }
+
+    /// Set a position parameter in its own unit (see [`PARAMS`]). NaNs, values outside of the
+    /// declared range and cartesian values that would put the source inside the head are rejected
+    /// and leave the position unchanged.
     pub fn set_param(&mut self, param: CoordParam, value: f32) -> Result<(), Error> {
+        let value = param.validate(value)?;
+
+        let (azimuth, elevation, distance) = match param {
+            CoordParam::Azimuth => (value, self.elevation, self.distance),
+            CoordParam::Elevation => (self.azimuth, value, self.distance),
+            CoordParam::Distance => (self.azimuth, self.elevation, value),
+            CoordParam::Xcoord | CoordParam::Ycoord | CoordParam::Zcoord => {
+                let (mut x, mut y, mut z) =
+                    spherical_to_cartesian(self.azimuth, self.elevation, self.distance);
+                match param {
+                    CoordParam::Xcoord => x = value,
+                    CoordParam::Ycoord => y = value,
+                    _ => z = value,
+                }
+
+                let (azimuth, elevation, distance) = cartesian_to_spherical(x, y, z);
+                if distance < MIN_DISTANCE {
+                    return Err(Error::InvalidValue { param, value });
+                }
+                // keep the azimuth when moving through the poles
+                let azimuth = if x == 0.0 && y == 0.0 { self.azimuth } else { azimuth };
+
+                (azimuth, elevation, distance.min(MAX_DISTANCE))
+            }
+        };
+
+        self.set_spherical(azimuth, elevation, distance);
+
+        Ok(())
+    }
+
+    /// The current value of a position parameter in its own unit.
+    pub fn get_param(&self, param: CoordParam) -> f32 {
+        let (x, y, z) = spherical_to_cartesian(self.azimuth, self.elevation, self.distance);
         match param {
-            CoordParam::Xcoord => { self.x = value; Ok(()) },
-            CoordParam::Ycoord => { self.y = value; Ok(()) },
-            CoordParam::Zcoord => { self.z = value; Ok(()) },
+            CoordParam::Xcoord => x,
+            CoordParam::Ycoord => y,
+            CoordParam::Zcoord => z,
+            CoordParam::Azimuth => self.azimuth,
+            CoordParam::Elevation => self.elevation,
+            CoordParam::Distance => self.distance,
+        }
+    }
+
+    /// The declared parameters, for hosts and command line tools to build their UI from.
+    pub fn params() -> &'static [ParamInfo] {
+        &PARAMS
+    }
+
+    /// Set all three cartesian coordinates at once, the filter is only looked up again if the
+    /// position actually changed. Invalid positions are ignored, this is meant for the audio thread
+    /// where there is nobody to report an error to.
+    pub fn set_position(&mut self, x: f32, y: f32, z: f32) {
+        let (azimuth, elevation, distance) = cartesian_to_spherical(x, y, z);
+        if distance.is_finite() && distance >= MIN_DISTANCE {
+            self.set_spherical(azimuth, elevation, distance.min(MAX_DISTANCE));
+        }
+    }
+
+    /// Like [`set_position`](Self::set_position) in spherical coordinates.
+    pub fn set_spherical_position(&mut self, azimuth: f32, elevation: f32, distance: f32) {
+        if azimuth.is_finite() && elevation.is_finite() && distance.is_finite() {
+            self.set_spherical(
+                azimuth,
+                elevation.clamp(-90.0, 90.0),
+                distance.clamp(MIN_DISTANCE, MAX_DISTANCE),
+            );
+        }
+    }
+
+    fn set_spherical(&mut self, azimuth: f32, elevation: f32, distance: f32) {
+        // wrap to -180..180
+        let azimuth = (azimuth + 180.0).rem_euclid(360.0) - 180.0;
+        if (azimuth, elevation, distance) != (self.azimuth, self.elevation, self.distance) {
+            (self.azimuth, self.elevation, self.distance) = (azimuth, elevation, distance);
+            self.filter_dirty = true;
+        }
+    }
+
+    /// Personalise the HRIRs to a head `circumference` in cm, see [`HeadModel::apply`].
+    pub fn set_head_size(&mut self, circumference: f32, pinna_shift: bool) {
+        if circumference != self.head_size || pinna_shift != self.pinna_shift {
+            self.head_size = circumference;
+            self.pinna_shift = pinna_shift;
+            self.backend.set_head_size(circumference, pinna_shift);
+            self.filter_dirty = true;
+        }
+    }
+
+    pub fn trajectory(&self) -> &Trajectory {
+        &self.trajectory
+    }
+
+    /// The automatic motion applied on top of the position parameters.
+    pub fn trajectory_mut(&mut self) -> &mut Trajectory {
+        &mut self.trajectory
+    }
+
+    /// Jump to `cycles` into the trajectory and start a new control block, so the motion from here
+    /// on is exactly the same as when rendering started at this point.
+    pub fn restart_trajectory(&mut self, cycles: f64) {
+        self.trajectory.set_cycles(cycles);
+        self.control_pos = 0;
+    }
+
+    /// Lock the trajectory to the host transport, one cycle lasting `division`. Call this before
+    /// every `process` with the transport at the start of the block.
+    ///
+    /// While playing the phase follows the song position, restarting on every jump (loops,
+    /// locating, starting playback) so renders are repeatable. While stopped the source holds still.
+    pub fn sync_trajectory(&mut self, transport: &TransportState, division: NoteDivision) {
+        let trajectory = &mut self.trajectory;
+        if !transport.playing {
+            trajectory.set_rate_hz(0.0);
+            self.transport_locked = false;
+            return;
+        }
+
+        trajectory.set_synced_rate(division, transport.tempo, transport.beats_per_bar);
+        let Some(cycles) = transport.cycles(division).filter(|_| trajectory.rate_hz() > 0.0) else {
+            // no song position, just run at the tempo
+            self.transport_locked = false;
+            return;
+        };
+
+        // where the trajectory is now, halfway through a control block it only moves on at the end
+        let samples_per_cycle = self.sample_rate as f64 / trajectory.rate_hz();
+        let current = trajectory.cycles() + self.control_pos as f64 / samples_per_cycle;
+        if !self.transport_locked || ((cycles - current) * samples_per_cycle).abs() > JUMP_TOLERANCE
+        {
+            self.restart_trajectory(cycles);
+            self.transport_locked = true;
         }
     }
 
+    /// The position that is actually being rendered, including the trajectory.
+    pub fn rendered_position(&self) -> (f32, f32, f32) {
+        self.rendered
+    }
+
+    /// Doppler shift for sources moving towards or away from the listener, `amount` between 0 (off)
+    /// and 1 (physically correct) and the speed of sound in m/s.
+    pub fn set_doppler(&mut self, amount: f32, speed_of_sound: f32) {
+        self.doppler.set_amount(amount);
+        self.doppler.set_speed_of_sound(speed_of_sound);
+    }
+
+    pub fn set_headphone_eq(&mut self, hp_eq: Option<HeadphoneEq>) {
+        self.hp_eq = hp_eq;
+    }
+
+    pub fn set_headphone_eq_bypass(&mut self, bypass: bool) {
+        self.hp_eq_bypass = bypass;
+    }
+
+    pub fn filter_len(&self) -> usize {
+        self.backend.filter_len()
+    }
+
+    /// How many samples after the input the direct sound reaches the nearer ear for the direction
+    /// it arrives earliest from: the backend's (see [`HrtfBackend::latency_samples`]) plus any
+    /// headphone EQ FIR delay. Hosts compensate for this so the binaural signal lines up with the
+    /// dry tracks. The Doppler propagation delay is part of the effect and not included. Changes
+    /// with the engine and headphone EQ settings only, so automating anything else never makes
+    /// the host recalculate its delay compensation.
+    pub fn latency_samples(&self) -> u32 {
+        let hp_eq = match &self.hp_eq {
+            Some(hp_eq) if !self.hp_eq_bypass => hp_eq.latency_samples(),
+            _ => 0,
+        };
+
+        self.backend.latency_samples() + hp_eq
+    }
+
+    /// The HRIR pair the backend is currently using, including the head size personalisation.
+    /// `None` for backends that interpolate internally.
+    pub fn filter(&self) -> Option<&Filter> {
+        self.backend.filter()
+    }
+
+    /// Changes whenever [`filter`](Self::filter) does.
+    pub fn filter_generation(&self) -> u64 {
+        self.filter_generation
+    }
+
+    fn update_filter(&mut self, position: (f32, f32, f32)) {
+        let (x, y, z) = spherical_to_cartesian(position.0, position.1, position.2);
+        self.backend.set_position(x, y, z);
+
+        self.rendered = position;
+        self.filter_generation += 1;
+        self.filter_dirty = false;
+    }
+}
+
+/// Convert azimuth/elevation in degrees and distance in metres to SOFA cartesian coordinates
+/// (+x front, +y left, +z up).
+pub fn spherical_to_cartesian(azimuth: f32, elevation: f32, distance: f32) -> (f32, f32, f32) {
+    let (az, el) = (azimuth.to_radians(), elevation.to_radians());
+
+    (
+        distance * az.cos() * el.cos(),
+        distance * az.sin() * el.cos(),
+        distance * el.sin(),
+    )
+}
+
+/// Convert SOFA cartesian coordinates to azimuth/elevation in degrees and distance in metres.
+pub fn cartesian_to_spherical(x: f32, y: f32, z: f32) -> (f32, f32, f32) {
+    let horizontal = x.hypot(y);
+
+    (
+        y.atan2(x).to_degrees(),
+        z.atan2(horizontal).to_degrees(),
+        horizontal.hypot(z),
+    )
 }
 
 #[cfg(test)]
 mod tests{
     use super::*;
+    use crate::audio_file;
+    use crate::fixtures::{self, TempSofa};
+    use crate::hrtf_backend::{MemoryBackend, ONSET_THRESHOLD};
+    use crate::sofa_writer::HrirSet;
+    use crate::trajectory::{NoteDivision, Shape, TransportState};
+    use assert_approx_eq::assert_approx_eq;
     use rand::prelude::*;
-    use std::time::Instant;
+    use std::path::Path;
 
-    #[test]
-    fn test_audio() {
-        let x = 0.0; // front-back
-        let y = -1.0; // left-right
-        let z = 0.0; // up-down
-        // let sample_rate_hz = 48000.0;
-        let mut reader = WavReader::open("/Users/Owen/Documents/GitHub/ase-project/audio/Melody_mono.wav").unwrap();
-        let spec = reader.spec();
-        dbg!(spec.sample_rate);
-
-        // Create a new spec with the desired sample rate and channels
-        let output_spec = hound::WavSpec {
-            channels: 2,                  // Stereo output
-            sample_rate: 44100,           // Desired sample rate
-            bits_per_sample: spec.bits_per_sample,
-            sample_format: spec.sample_format,
+    const GOLDEN_FILTER_LEN: usize = 256;
+    /// Largest difference from a golden sample, a few steps of its 16-bit resolution
+    const GOLDEN_TOLERANCE: f32 = 1e-4;
+    const GOLDEN_INPUT_GAIN: f32 = 0.5;
+
+    /// Where a golden render gets the synthetic spherical head from.
+    #[derive(Debug, Clone, Copy)]
+    enum GoldenSource {
+        /// [`MemoryBackend`], independent of the libmysofa version
+        Memory,
+        /// Written to a .sofa file and loaded like a user's dataset, then rendered through
+        /// libmysofa by `engine` for a head of `head_size` cm with the pinna shift on
+        Sofa {
+            engine: ConvolutionEngine,
+            head_size: f32,
+        },
+    }
+
+    /// Render one of the bundled audio files with the synthetic spherical head from a fixed
+    /// position and compare it to `fixtures/golden/{name}.wav`. Run with `ASE_UPDATE_GOLDEN=1` to
+    /// write the golden files instead, after a deliberate change to the output.
+    fn check_golden(
+        name: &str,
+        audio: &str,
+        (azimuth, elevation, distance): (f32, f32, f32),
+        source: GoldenSource,
+    ) {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let (mut input, sample_rate) =
+            audio_file::read_mono(root.join("audio").join(audio)).unwrap();
+        // headroom for libmysofa's loudness normalisation, the golden files can't store clipping
+        for sample in &mut input {
+            *sample *= GOLDEN_INPUT_GAIN;
+        }
+
+        let set = fixtures::spherical_head(sample_rate as f64, GOLDEN_FILTER_LEN);
+        let mut efx = match source {
+            GoldenSource::Memory => {
+                let backend = Box::new(MemoryBackend::new(set));
+                SpatializerEfx::with_backend(backend, sample_rate as f32, 512)
+            }
+            GoldenSource::Sofa { engine, head_size } => {
+                let file = TempSofa::new(&format!("golden-{name}"), &set).unwrap();
+                let sofa = crate::sofa_info::open_sofa(file.path(), sample_rate as f32).unwrap();
+                let mut efx = SpatializerEfx::new(sofa, sample_rate as f32, 512).unwrap();
+                efx.set_engine(engine);
+                efx.set_head_size(head_size, true);
+                efx
+            }
         };
-        let mut writer = WavWriter::create("output.wav", output_spec).unwrap();        
-
-        // let left_samples = reader.samples::<f32>().len() as usize;
-        let input: Vec<f32> = reader.samples::<f32>().take(512*400).map(Result::unwrap).collect();
-        let left_samples = 512*400;
-        // dbg!(input.len());
-        
-        // load in sofa part
-        let start_time = Instant::now(); // Start timing
-        let sofa = OpenOptions::new()
-            .sample_rate(48000.0)
-            .open("/Users/Owen/Documents/GitHub/ase-project/SOFA-data/HRIR_FULL2DEG.sofa")
-            .unwrap();
-        let end_time = Instant::now(); // End timing
-        let elapsed_time = end_time - start_time;
-        println!("Time taken to load the sofa file: {:?}", elapsed_time);
-        
-        let filt_len = sofa.filter_len();
-        let mut filter = Filter::new(filt_len);
-
-        // extract IR part
-        let start_time = Instant::now(); // Start timing
-        sofa.filter(x, y, z, &mut filter);
-        let mut render = Renderer::builder(filt_len)
-            .with_sample_rate(48000.0)
-            .with_partition_len(64)
-            .build()
-            .unwrap();
-        render.set_filter(&filter);
-        let end_time = Instant::now(); // End timing
-        let elapsed_time = end_time - start_time;
-        println!("Time taken to extract IR: {:?}", elapsed_time);
-
-        let mut left: Vec<f32> = vec![0.0; left_samples];
-        let mut right: Vec<f32> = vec![0.0; left_samples];
-
-        // process part
-        let start_time = Instant::now(); // Start timing
-        render.process_block(&input, &mut left, &mut right).unwrap();
-        let end_time = Instant::now(); // End timing
-        let elapsed_time = end_time - start_time;
-        println!("Time taken to process: {:?}", elapsed_time);        
-
-        // Write interleaved samples
-        for i in 0..left.len() {
-            writer.write_sample(left[i]).unwrap();
-            writer.write_sample(right[i]).unwrap();
-        }
-
-        // Finalize the WAV file
+        efx.set_spherical_position(azimuth, elevation, distance);
+
+        // long enough for the tail to ring out
+        let len = input.len() + GOLDEN_FILTER_LEN + PARTITION_LEN;
+        let mut output = [input.clone(), input];
+        for ear in output.iter_mut() {
+            ear.resize(len, 0.0);
+        }
+        let (left, right) = output.split_at_mut(1);
+        for (left, right) in left[0].chunks_mut(512).zip(right[0].chunks_mut(512)) {
+            efx.process(&mut [left, right]);
+        }
+
+        let path = root.join("fixtures/golden").join(format!("{name}.wav"));
+        if std::env::var_os("ASE_UPDATE_GOLDEN").is_some() {
+            write_golden(&path, &output, sample_rate);
+            return;
+        }
+
+        let expected = read_golden(&path).unwrap_or_else(|err| {
+            panic!("{}: {err}, run with ASE_UPDATE_GOLDEN=1 to create it", path.display())
+        });
+        for ear in 0..2 {
+            assert_eq!(output[ear].len(), expected[ear].len(), "{name}");
+            for (n, (actual, expected)) in output[ear].iter().zip(&expected[ear]).enumerate() {
+                let diff = (actual - expected).abs();
+                assert!(
+                    diff <= GOLDEN_TOLERANCE,
+                    "{name}: ear {ear}, sample {n}: {actual} instead of {expected}"
+                );
+            }
+        }
+    }
+
+    /// Golden renders are kept whole as 16-bit stereo WAV files, so every sample is compared and
+    /// they can be listened to.
+    fn write_golden(path: &Path, output: &[Vec<f32>; 2], sample_rate: u32) {
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let mut writer = hound::WavWriter::create(path, spec).unwrap();
+        for (left, right) in output[0].iter().zip(&output[1]) {
+            for sample in [left, right] {
+                assert!(sample.abs() < 1.0, "{}: the render clips", path.display());
+                writer.write_sample((sample * i16::MAX as f32).round() as i16).unwrap();
+            }
+        }
         writer.finalize().unwrap();
+    }
+
+    fn read_golden(path: &Path) -> Result<[Vec<f32>; 2], hound::Error> {
+        let mut reader = hound::WavReader::open(path)?;
+        let mut golden = [Vec::new(), Vec::new()];
+        for (n, sample) in reader.samples::<i16>().enumerate() {
+            golden[n % 2].push(sample? as f32 / i16::MAX as f32);
+        }
+        Ok(golden)
+    }
+
+    #[test]
+    fn test_golden_melody() {
+        let source = GoldenSource::Memory;
+        check_golden("melody_left", "Melody_mono.wav", (90.0, 0.0, 1.0), source);
+        let position = (-135.0, 30.0, 2.0);
+        check_golden("melody_back_right_above", "Melody_mono.wav", position, source);
+    }
+
+    #[test]
+    fn test_golden_guitar() {
+        let source = GoldenSource::Memory;
+        check_golden("guitar_front_right", "guitar_stereo.wav", (-30.0, 0.0, 1.0), source);
+        check_golden("guitar_left_below", "guitar_stereo.wav", (60.0, -30.0, 1.0), source);
+    }
+
+    /// The shipped path: the .sofa load, libmysofa's lookup and loudness normalisation, the head
+    /// model and both convolution engines. The positions lie on the measurement grid, where
+    /// libmysofa returns the measured IRs without interpolating.
+    #[test]
+    fn test_golden_sofa() {
+        let source = GoldenSource::Sofa {
+            engine: ConvolutionEngine::Sofar,
+            head_size: head_model::REFERENCE_CIRCUMFERENCE,
+        };
+        let position = (-135.0, 30.0, 2.0);
+        check_golden("sofa_melody_back_right_above", "Melody_mono.wav", position, source);
 
-    }    
+        let source = GoldenSource::Sofa {
+            engine: ConvolutionEngine::OverlapAdd,
+            head_size: head_model::MAX_CIRCUMFERENCE,
+        };
+        let position = (60.0, -30.0, 1.0);
+        check_golden("sofa_ola_guitar_large_head", "guitar_stereo.wav", position, source);
+    }
+
+    /// A tiny dataset on the horizontal plane where each ear's IR is a delayed, scaled impulse.
+    fn test_efx(name: &str, sample_rate: f32, max_block_size: usize) -> SpatializerEfx {
+        let mut set = HrirSet::new(sample_rate as f64, 32);
+        for az in (0..360).step_by(15) {
+            let lateral = (az as f32).to_radians().sin();
+            let mut left = [0.0; 32];
+            let mut right = [0.0; 32];
+            left[(4.0 - 3.0 * lateral) as usize] = 0.5 + 0.25 * lateral;
+            right[(4.0 + 3.0 * lateral) as usize] = 0.5 - 0.25 * lateral;
+            set.push([az as f64, 0.0, 1.0], &left, &right);
+        }
+
+        let file = TempSofa::new(name, &set).unwrap();
+        let sofa = crate::sofa_info::open_sofa(file.path(), sample_rate).unwrap();
+
+        SpatializerEfx::new(sofa, sample_rate, max_block_size).unwrap()
+    }
 
     #[test]
     fn test_run() {
-        let x = 1.0;
-        let y = 0.0;
-        let z = 0.0;
+        let (x, y, z) = (1.0, 0.0, 0.0);
         let sample_rate_hz = 48000.0;
-        // let input = [
-        //     [0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0],
-        //     [1.0, 0.0, 1.0, 0.0, 1.0, 0.0, 1.0, 0.0, 1.0, 0.0],
-        // ];
 
         let mut input= [[0.0; 512]; 2];
         // Fill the vector with random values
@@ -173,12 +815,293 @@ mod tests{
                 *val = rng.gen_range(-1.0..1.0); // Generate random value between -1.0 and 1.0
             }
         }     
+
+        // one call with the whole input
+        let mut efx = test_efx("run-whole", sample_rate_hz, 512);
+        efx.set_position(x, y, z);
         let mut output = input;
         let (buf0, buf1) = output.split_at_mut(1);
-        let bufs: &mut[&mut [f32]] = &mut [&mut buf0[0], &mut buf1[0]];       
+        efx.process(&mut [&mut buf0[0], &mut buf1[0]]);
 
-        // let mut efx = SpatializerEfx::new(x, y, z, sample_rate_hz);
-        // efx.process(bufs)
+        // the same input in odd sized blocks, with scratch buffers smaller than the block
+        let mut efx = test_efx("run-blocks", sample_rate_hz, 100);
+        efx.set_position(x, y, z);
+        let mut blocks = input;
+        let (buf0, buf1) = blocks.split_at_mut(1);
+        for (l, r) in buf0[0].chunks_mut(129).zip(buf1[0].chunks_mut(129)) {
+            efx.process(&mut [l, r]);
+        }
 
+        for (a, b) in output.iter().flatten().zip(blocks.iter().flatten()) {
+            assert!(a.is_finite());
+            assert!((a - b).abs() < 1e-5);
+        }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_trajectory_block_size_independent() {
+        let mut input = [[0.0; 2048]; 2];
+        let mut rng = StdRng::seed_from_u64(7);
+        for val in input.iter_mut().flatten() {
+            *val = rng.gen_range(-1.0..1.0);
+        }
+
+        let render = |name: &str, block_len: usize| {
+            let mut efx = test_efx(name, 48000.0, 256);
+            efx.trajectory_mut().set_shape(Shape::Circle);
+            efx.trajectory_mut().set_rate_hz(20.0);
+
+            let mut output = input;
+            let (buf0, buf1) = output.split_at_mut(1);
+            for (l, r) in buf0[0].chunks_mut(block_len).zip(buf1[0].chunks_mut(block_len)) {
+                efx.process(&mut [l, r]);
+            }
+            (output, efx.rendered_position())
+        };
+
+        let (whole, position) = render("trajectory-whole", 2048);
+        let (blocks, block_position) = render("trajectory-blocks", 37);
+        assert_eq!(position, block_position);
+        // the source really moved, the last control block starts 31 * 64 samples in
+        let turns = (31 * CONTROL_LEN) as f32 * 20.0 / 48000.0;
+        assert_approx_eq!(position.0, turns * 360.0 - 360.0, 1e-2);
+
+        for (a, b) in whole.iter().flatten().zip(blocks.iter().flatten()) {
+            assert!((a - b).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_motion_after_static_blocks() {
+        let block_len = 512;
+        let mut efx = test_efx("motion-after-static", 48000.0, block_len);
+        let (mut left, mut right) = (vec![0.0; block_len], vec![0.0; block_len]);
+        for _ in 0..3 {
+            efx.process(&mut [&mut left, &mut right]);
+        }
+        assert_eq!(efx.rendered_position(), (0.0, 0.0, 1.0));
+
+        // the first control block of the motion starts with the block after the switch
+        efx.trajectory_mut().set_shape(Shape::Circle);
+        efx.trajectory_mut().set_rate_hz(20.0);
+        for _ in 0..2 {
+            efx.process(&mut [&mut left, &mut right]);
+        }
+        let turns = (2 * block_len - CONTROL_LEN) as f32 * 20.0 / 48000.0;
+        assert_approx_eq!(efx.rendered_position().0, turns * 360.0, 1e-2);
+    }
+
+    #[test]
+    fn test_transport_jump_restarts() {
+        let block_len = 512;
+        let render = |name: &str, start_beats: f64, blocks: usize| {
+            let mut efx = test_efx(name, 48000.0, block_len);
+            efx.trajectory_mut().set_shape(Shape::Circle);
+
+            // at 112.5 BPM a quarter note is exactly 50 blocks
+            let mut transport = TransportState {
+                playing: true,
+                tempo: 112.5,
+                pos_beats: Some(start_beats),
+                ..Default::default()
+            };
+            let mut positions = Vec::new();
+            for _ in 0..blocks {
+                efx.sync_trajectory(&transport, NoteDivision::Quarter);
+                let (mut left, mut right) = (vec![0.0; block_len], vec![0.0; block_len]);
+                efx.process(&mut [&mut left, &mut right]);
+                positions.push(efx.rendered_position());
+
+                *transport.pos_beats.as_mut().unwrap() += 0.02;
+            }
+            positions
+        };
+
+        // starting halfway through the beat gives the second half of a render from the start
+        let from_start = render("transport-start", 0.0, 50);
+        let from_middle = render("transport-middle", 0.5, 25);
+        for (a, b) in from_start[25..].iter().zip(&from_middle) {
+            assert_approx_eq!(a.0, b.0, 1e-3);
+        }
+        // the last control block of the first block starts 448 samples in
+        assert_approx_eq!(from_middle[0].0, (0.5 + 448.0 / 25600.0) * 360.0 - 360.0, 1e-2);
+
+        // a stopped transport holds the source still
+        let mut efx = test_efx("transport-stopped", 48000.0, block_len);
+        efx.trajectory_mut().set_shape(Shape::Circle);
+        let stopped = TransportState::default();
+        for _ in 0..10 {
+            efx.sync_trajectory(&stopped, NoteDivision::Quarter);
+            let (mut left, mut right) = (vec![0.0; block_len], vec![0.0; block_len]);
+            efx.process(&mut [&mut left, &mut right]);
+            assert_eq!(efx.rendered_position().0, 0.0);
+        }
+    }
+
+    #[test]
+    fn test_param_ranges() {
+        let mut efx = test_efx("params", 48000.0, 64);
+
+        efx.set_param(CoordParam::Azimuth, 270.0).unwrap();
+        assert_approx_eq!(efx.get_param(CoordParam::Azimuth), -90.0);
+        assert_approx_eq!(efx.get_param(CoordParam::Ycoord), -1.0);
+
+        efx.set_param(CoordParam::Distance, 2.0).unwrap();
+        efx.set_param(CoordParam::Xcoord, 2.0).unwrap();
+        assert_approx_eq!(efx.get_param(CoordParam::Azimuth), -45.0);
+        assert_approx_eq!(efx.get_param(CoordParam::Distance), 8f32.sqrt());
+
+        for (param, value) in [
+            (CoordParam::Elevation, f32::NAN),
+            (CoordParam::Elevation, 91.0),
+            (CoordParam::Distance, 0.0),
+            (CoordParam::Xcoord, f32::INFINITY),
+        ] {
+            assert!(matches!(
+                efx.set_param(param, value),
+                Err(Error::InvalidValue { .. })
+            ));
+        }
+        // rejected values leave the position alone
+        assert_approx_eq!(efx.get_param(CoordParam::Distance), 8f32.sqrt());
+
+        // moving straight up keeps the azimuth
+        efx.set_param(CoordParam::Elevation, 90.0).unwrap();
+        assert_approx_eq!(efx.get_param(CoordParam::Azimuth), -45.0);
+
+        assert_eq!(SpatializerEfx::params().len(), 6);
+    }
+
+    #[test]
+    fn test_reset_clears_tail() {
+        let mut efx = test_efx("reset", 48000.0, 64);
+        efx.set_position(0.0, 1.0, 0.0);
+
+        let mut left = [1.0; 64];
+        let mut right = [1.0; 64];
+        efx.process(&mut [&mut left, &mut right]);
+        efx.reset();
+
+        let mut left = [0.0; 64];
+        let mut right = [0.0; 64];
+        efx.process(&mut [&mut left, &mut right]);
+        assert!(left.iter().chain(right.iter()).all(|s| s.abs() < 1e-6));
+    }
+
+    #[test]
+    fn test_latency_impulse() {
+        // the first output sample within the onset threshold of the peak
+        let onset = |efx: &mut SpatializerEfx| {
+            let mut left = [0.0; 256];
+            let mut right = [0.0; 256];
+            left[0] = 1.0;
+            right[0] = 1.0;
+            efx.reset();
+            efx.process(&mut [&mut left, &mut right]);
+
+            let peak = left.iter().chain(&right).fold(0.0f32, |peak, s| peak.max(s.abs()));
+            let first = |ch: &[f32]| ch.iter().position(|s| s.abs() >= peak * ONSET_THRESHOLD);
+            first(&left).unwrap().min(first(&right).unwrap()) as u32
+        };
+
+        // the left ear at 90 degrees is the earliest IR of the test dataset, one sample in
+        let mut efx = test_efx("latency", 48000.0, 256);
+        efx.set_spherical_position(90.0, 0.0, 1.0);
+        assert_eq!(efx.latency_samples(), 1);
+        assert_eq!(onset(&mut efx), efx.latency_samples());
+
+        // other directions arrive later, never earlier
+        efx.set_spherical_position(0.0, 0.0, 1.0);
+        assert!(onset(&mut efx) > efx.latency_samples());
+
+        // the pinna stretch of a smaller head keeps the direct sound where it was reported
+        efx.set_spherical_position(90.0, 0.0, 1.0);
+        efx.set_head_size(head_model::MIN_CIRCUMFERENCE, true);
+        assert_eq!(efx.latency_samples(), 1);
+        assert_eq!(onset(&mut efx), 1);
+        efx.set_head_size(head_model::REFERENCE_CIRCUMFERENCE, false);
+
+        // a linear phase headphone EQ adds its delay
+        let profile = crate::headphone_eq::EqProfile::Fir {
+            left: vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0],
+            right: vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0],
+            sample_rate: 48000,
+            preamp_db: 0.0,
+        };
+        efx.set_headphone_eq(Some(HeadphoneEq::new(&profile, 48000.0).unwrap()));
+        efx.set_spherical_position(90.0, 0.0, 1.0);
+        assert_eq!(efx.latency_samples(), 4);
+        assert_eq!(onset(&mut efx), 4);
+
+        efx.set_headphone_eq_bypass(true);
+        assert_eq!(efx.latency_samples(), 1);
+
+        efx.set_engine(ConvolutionEngine::OverlapAdd);
+        assert_eq!(efx.latency_samples(), PARTITION_LEN as u32 + 1);
+        assert_eq!(onset(&mut efx), efx.latency_samples());
+    }
+
+    #[test]
+    fn test_engines_match() {
+        let mut input = [[0.0; 1024]; 2];
+        let mut rng = StdRng::seed_from_u64(5);
+        for val in input.iter_mut().flatten() {
+            *val = rng.gen_range(-1.0..1.0);
+        }
+
+        let render = |name: &str, engine: ConvolutionEngine| {
+            let mut efx = test_efx(name, 48000.0, 100);
+            efx.set_engine(engine);
+            efx.set_spherical_position(60.0, 0.0, 1.0);
+            let mut output = input;
+            let (buf0, buf1) = output.split_at_mut(1);
+            for (l, r) in buf0[0].chunks_mut(77).zip(buf1[0].chunks_mut(77)) {
+                efx.process(&mut [l, r]);
+            }
+            output
+        };
+
+        let sofar = render("engine-sofar", ConvolutionEngine::Sofar);
+        let ola = render("engine-ola", ConvolutionEngine::OverlapAdd);
+        for ear in 0..2 {
+            for n in PARTITION_LEN..1024 {
+                assert_approx_eq!(ola[ear][n], sofar[ear][n - PARTITION_LEN], 1e-4);
+            }
+        }
+
+        // a source circling through the 15 degree steps of the dataset gets a new filter every
+        // control block. The overlap-add engine crossfades to it, so a sine stays about as smooth
+        // as it is, and otherwise keeps up with the renderer.
+        let sine: Vec<f32> = (0..9600)
+            .map(|n| 0.5 * (std::f32::consts::TAU * 250.0 * n as f32 / 48000.0).sin())
+            .collect();
+        let render_moving = |name: &str, engine: ConvolutionEngine| {
+            let mut efx = test_efx(name, 48000.0, 100);
+            efx.set_engine(engine);
+            efx.set_spherical_position(60.0, 0.0, 1.0);
+            efx.trajectory_mut().set_shape(Shape::Circle);
+            efx.trajectory_mut().set_rate_hz(2.0);
+            let mut output = [sine.clone(), sine.clone()];
+            let (buf0, buf1) = output.split_at_mut(1);
+            for (l, r) in buf0[0].chunks_mut(77).zip(buf1[0].chunks_mut(77)) {
+                efx.process(&mut [l, r]);
+            }
+            output
+        };
+
+        let sofar = render_moving("engine-sofar-moving", ConvolutionEngine::Sofar);
+        let ola = render_moving("engine-ola-moving", ConvolutionEngine::OverlapAdd);
+        // the steepest the sine gets through the loudest HRIR tap
+        let max_slope = 0.75 * 0.5 * std::f32::consts::TAU * 250.0 / 48000.0;
+        for ear in 0..2 {
+            for n in PARTITION_LEN..sine.len() {
+                let diff = (ola[ear][n] - sofar[ear][n - PARTITION_LEN]).abs();
+                assert!(diff < 0.05, "{diff} in ear {ear} at sample {n}");
+            }
+            for (n, pair) in ola[ear].windows(2).enumerate().skip(2 * PARTITION_LEN) {
+                let jump = (pair[1] - pair[0]).abs();
+                assert!(jump < 3.0 * max_slope, "{jump} in ear {ear} at sample {n}");
+            }
+        }
+    }
+}
@@ -0,0 +1,447 @@
+// Automatic source motion: circles, figure-eights, random walks, ping-pong, keyframe paths and
+// fly-bys around the position set by the user.
+//
+// Every shape is a pure function of the phase (in cycles), so the motion only depends on how many
+// samples have been processed and not on how the host splits them into blocks. Restarting from the
+// same phase always gives the same positions. `SpatializerEfx` evaluates it once per control block
+// of `CONTROL_LEN` samples, see there.
+
+use nih_plug::prelude::Enum;
+
+use crate::automation::Automation;
+use crate::spatializer_efx::{MAX_DISTANCE, MIN_DISTANCE};
+
+use std::f64::consts::TAU;
+
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shape {
+    /// The source stays where it was put
+    #[name = "Off"]
+    Static,
+    /// Full turns around the listener, counterclockwise when looking down
+    #[name = "Circle"]
+    Circle,
+    /// Side to side while bobbing up and down twice as fast
+    #[name = "Figure-Eight"]
+    FigureEight,
+    /// Glides to a new random direction within the width every cycle
+    #[name = "Random Walk"]
+    RandomWalk,
+    /// Back and forth between the two points `width` degrees to either side
+    #[name = "Ping-Pong"]
+    PingPong,
+    /// The keyframes of a user automation file, one pass per cycle
+    #[name = "Path"]
+    Path,
+    /// Along a straight line past the listener and back, closest at the centre and `width`
+    /// degrees to either side at the ends. The only shape besides a path that changes the
+    /// distance, and with it the Doppler shift.
+    #[name = "Fly-By"]
+    FlyBy,
+}
+
+/// Length of one trajectory cycle when it is synced to the host tempo.
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoteDivision {
+    #[name = "1/16"]
+    Sixteenth,
+    #[name = "1/8"]
+    Eighth,
+    #[name = "1/4"]
+    Quarter,
+    #[name = "1/2"]
+    Half,
+    #[name = "1 Bar"]
+    Bar,
+    #[name = "2 Bars"]
+    TwoBars,
+    #[name = "4 Bars"]
+    FourBars,
+    #[name = "8 Bars"]
+    EightBars,
+}
+
+impl NoteDivision {
+    /// The length in quarter notes.
+    pub fn beats(self, beats_per_bar: f64) -> f64 {
+        match self {
+            NoteDivision::Sixteenth => 0.25,
+            NoteDivision::Eighth => 0.5,
+            NoteDivision::Quarter => 1.0,
+            NoteDivision::Half => 2.0,
+            NoteDivision::Bar => beats_per_bar,
+            NoteDivision::TwoBars => 2.0 * beats_per_bar,
+            NoteDivision::FourBars => 4.0 * beats_per_bar,
+            NoteDivision::EightBars => 8.0 * beats_per_bar,
+        }
+    }
+}
+
+/// The host transport at the start of a block, as far as the trajectory cares about it. Kept
+/// separate from nih_plug's `Transport` so the offline tools and tests can make their own.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TransportState {
+    pub playing: bool,
+    /// Beats per minute
+    pub tempo: f64,
+    /// Bar length in quarter notes
+    pub beats_per_bar: f64,
+    /// Song position in quarter notes, if the host reports it
+    pub pos_beats: Option<f64>,
+    /// The bar `pos_beats` falls in and where it starts, for hosts with time signature changes
+    pub bar: Option<(i32, f64)>,
+}
+
+impl Default for TransportState {
+    fn default() -> Self {
+        TransportState {
+            playing: false,
+            tempo: 120.0,
+            beats_per_bar: 4.0,
+            pos_beats: None,
+            bar: None,
+        }
+    }
+}
+
+impl TransportState {
+    /// The number of `division` cycles since the start of the song, counted from the bar lines so
+    /// the motion lines up with the music even after a time signature change.
+    pub fn cycles(&self, division: NoteDivision) -> Option<f64> {
+        let pos_beats = self.pos_beats?;
+        let beats = division.beats(self.beats_per_bar);
+        let song_beats = match self.bar {
+            Some((bar_number, bar_start)) => {
+                bar_number as f64 * self.beats_per_bar + (pos_beats - bar_start)
+            }
+            None => pos_beats,
+        };
+
+        Some(song_beats / beats)
+    }
+}
+
+/// The widest angle a fly-by reaches, the line would be infinitely long at 90 degrees.
+const MAX_FLY_BY_ANGLE: f32 = 85.0;
+
+/// Seed for the random walk, fixed so renders are repeatable.
+const RANDOM_SEED: u64 = 0x5eed_a5e0;
+
+#[derive(Debug, Clone)]
+pub struct Trajectory {
+    shape: Shape,
+    sample_rate: f64,
+    /// Cycles per second
+    rate: f64,
+    /// Added to the phase, in cycles
+    phase_offset: f64,
+    /// How far the motion strays from the centre, in degrees
+    width: f32,
+    path: Option<Automation>,
+
+    /// Cycles elapsed since the last reset
+    cycles: f64,
+}
+
+impl Trajectory {
+    pub fn new(sample_rate: f32) -> Self {
+        Trajectory {
+            shape: Shape::Static,
+            sample_rate: sample_rate as f64,
+            rate: 0.1,
+            phase_offset: 0.0,
+            width: 45.0,
+            path: None,
+            cycles: 0.0,
+        }
+    }
+
+    pub fn shape(&self) -> Shape {
+        self.shape
+    }
+
+    pub fn set_shape(&mut self, shape: Shape) {
+        self.shape = shape;
+    }
+
+    /// Whether [`position`](Self::position) can return anything other than the centre.
+    pub fn is_moving(&self) -> bool {
+        match self.shape {
+            Shape::Static => false,
+            Shape::Path => self.path.is_some(),
+            _ => true,
+        }
+    }
+
+    /// Set the speed in cycles per second.
+    pub fn set_rate_hz(&mut self, rate: f32) {
+        if rate.is_finite() {
+            self.rate = rate.max(0.0) as f64;
+        }
+    }
+
+    /// Set the speed so one cycle lasts `division` at `tempo` BPM.
+    pub fn set_synced_rate(&mut self, division: NoteDivision, tempo: f64, beats_per_bar: f64) {
+        let beats = division.beats(beats_per_bar);
+        if tempo.is_finite() && tempo > 0.0 && beats > 0.0 {
+            self.rate = tempo / 60.0 / beats;
+        }
+    }
+
+    pub fn rate_hz(&self) -> f64 {
+        self.rate
+    }
+
+    /// Offset the motion by `degrees` of a cycle, e.g. to spread several sources around a circle.
+    pub fn set_phase(&mut self, degrees: f32) {
+        if degrees.is_finite() {
+            self.phase_offset = (degrees / 360.0) as f64;
+        }
+    }
+
+    pub fn set_width(&mut self, degrees: f32) {
+        if degrees.is_finite() {
+            self.width = degrees.clamp(0.0, 180.0);
+        }
+    }
+
+    /// The keyframes followed by [`Shape::Path`], relative to the centre azimuth.
+    pub fn set_path(&mut self, path: Option<Automation>) {
+        self.path = path;
+    }
+
+    /// Move `samples` further along the trajectory.
+    pub fn advance(&mut self, samples: usize) {
+        self.cycles += self.rate * samples as f64 / self.sample_rate;
+    }
+
+    /// Cycles elapsed since the last reset, without the phase offset.
+    pub fn cycles(&self) -> f64 {
+        self.cycles
+    }
+
+    /// Jump to a point in the motion, e.g. to follow the host transport.
+    pub fn set_cycles(&mut self, cycles: f64) {
+        if cycles.is_finite() {
+            self.cycles = cycles;
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.cycles = 0.0;
+    }
+
+    /// The (azimuth, elevation, distance) at the current phase when the user put the source at
+    /// `centre`. Azimuths are in -180..180 degrees, elevations in -90..90 and distances within the
+    /// range of the distance parameter, wherever a path puts its keyframes.
+    pub fn position(&self, centre: (f32, f32, f32)) -> (f32, f32, f32) {
+        let (azimuth, elevation, distance) = centre;
+        let phase = self.cycles + self.phase_offset;
+        let fract = phase.rem_euclid(1.0);
+        let width = self.width;
+
+        let (azimuth, elevation, distance) = match self.shape {
+            Shape::Static => (azimuth, elevation, distance),
+            Shape::Circle => (azimuth + 360.0 * fract as f32, elevation, distance),
+            Shape::FigureEight => (
+                azimuth + width * (TAU * fract).sin() as f32,
+                elevation + width / 2.0 * (2.0 * TAU * fract).sin() as f32,
+                distance,
+            ),
+            Shape::RandomWalk => {
+                let cycle = phase.floor() as i64;
+                let (from_az, from_el) = random_offset(cycle);
+                let (to_az, to_el) = random_offset(cycle + 1);
+                let t = smoothstep(fract as f32);
+
+                (
+                    azimuth + width * (from_az + (to_az - from_az) * t),
+                    elevation + width / 2.0 * (from_el + (to_el - from_el) * t),
+                    distance,
+                )
+            }
+            Shape::PingPong => {
+                // ease in and out at the turning points
+                let t = (1.0 - (TAU * fract).cos()) as f32 / 2.0;
+                (azimuth - width + 2.0 * width * t, elevation, distance)
+            }
+            Shape::Path => match &self.path {
+                Some(path) => {
+                    let (az, el, dist) = path.position_at(fract * path.duration());
+                    (azimuth + az, el, dist)
+                }
+                None => (azimuth, elevation, distance),
+            },
+            Shape::FlyBy => {
+                // square to the direction of the centre, easing in and out at the ends
+                let half_length = distance * width.min(MAX_FLY_BY_ANGLE).to_radians().tan();
+                let offset = -half_length * (TAU * fract).cos() as f32;
+                (
+                    azimuth + offset.atan2(distance).to_degrees(),
+                    elevation,
+                    distance.hypot(offset),
+                )
+            }
+        };
+
+        (
+            (azimuth + 180.0).rem_euclid(360.0) - 180.0,
+            elevation.clamp(-90.0, 90.0),
+            distance.clamp(MIN_DISTANCE, MAX_DISTANCE),
+        )
+    }
+}
+
+/// A repeatable pseudo random (azimuth, elevation) in -1..1 for every cycle of the random walk.
+fn random_offset(cycle: i64) -> (f32, f32) {
+    // splitmix64
+    let mut z = RANDOM_SEED.wrapping_add((cycle as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^= z >> 31;
+
+    let unit = |bits: u64| (bits & 0xffff_ffff) as f32 / u32::MAX as f32 * 2.0 - 1.0;
+    (unit(z), unit(z >> 32))
+}
+
+fn smoothstep(t: f32) -> f32 {
+    t * t * (3.0 - 2.0 * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::automation::Keyframe;
+    use assert_approx_eq::assert_approx_eq;
+
+    #[test]
+    fn test_block_size_independent() {
+        let centre = (10.0, 0.0, 1.5);
+        for shape in [
+            Shape::Circle,
+            Shape::FigureEight,
+            Shape::RandomWalk,
+            Shape::PingPong,
+        ] {
+            let mut whole = Trajectory::new(48000.0);
+            whole.set_shape(shape);
+            whole.set_rate_hz(0.7);
+            let mut split = whole.clone();
+
+            whole.advance(48000);
+            for _ in 0..48000 / 64 {
+                split.advance(64);
+            }
+
+            let (a, b) = (whole.position(centre), split.position(centre));
+            assert_approx_eq!(a.0, b.0, 1e-3);
+            assert_approx_eq!(a.1, b.1, 1e-3);
+            assert_eq!(a.2, 1.5);
+        }
+    }
+
+    #[test]
+    fn test_transport_cycles() {
+        let mut transport = TransportState {
+            playing: true,
+            tempo: 90.0,
+            beats_per_bar: 3.0,
+            pos_beats: Some(7.5),
+            bar: None,
+        };
+        assert_approx_eq!(transport.cycles(NoteDivision::Quarter).unwrap(), 7.5);
+        assert_approx_eq!(transport.cycles(NoteDivision::Bar).unwrap(), 2.5);
+        assert_approx_eq!(transport.cycles(NoteDivision::FourBars).unwrap(), 7.5 / 12.0);
+
+        // after a change from 4/4 to 3/4 at beat 8, bar 2 starts at beat 8
+        transport.bar = Some((2, 8.0));
+        transport.pos_beats = Some(9.5);
+        assert_approx_eq!(transport.cycles(NoteDivision::Bar).unwrap(), 2.5);
+
+        transport.pos_beats = None;
+        assert_eq!(transport.cycles(NoteDivision::Bar), None);
+    }
+
+    #[test]
+    fn test_shapes() {
+        let centre = (0.0, 0.0, 1.0);
+        let mut trajectory = Trajectory::new(1000.0);
+        trajectory.set_rate_hz(1.0);
+        trajectory.set_width(30.0);
+
+        // a quarter turn to the left
+        trajectory.set_shape(Shape::Circle);
+        trajectory.advance(250);
+        assert_approx_eq!(trajectory.position(centre).0, 90.0, 1e-3);
+
+        // halfway between the two points
+        trajectory.set_shape(Shape::PingPong);
+        assert_approx_eq!(trajectory.position(centre).0, 0.0, 1e-3);
+        trajectory.advance(250);
+        assert_approx_eq!(trajectory.position(centre).0, 30.0, 1e-3);
+
+        // the phase offset moves a second source to the opposite side
+        trajectory.set_phase(180.0);
+        assert_approx_eq!(trajectory.position(centre).0, -30.0, 1e-3);
+
+        // the random walk never leaves the width
+        trajectory.set_shape(Shape::RandomWalk);
+        for _ in 0..1000 {
+            trajectory.advance(37);
+            let (az, el, _) = trajectory.position(centre);
+            assert!(az.abs() <= 30.0 && el.abs() <= 15.0);
+        }
+
+        // paths are stretched to one pass per cycle
+        let path = Automation::new(vec![
+            Keyframe { time: 0.0, azimuth: 0.0, elevation: 0.0, distance: 1.0 },
+            Keyframe { time: 4.0, azimuth: 0.0, elevation: 40.0, distance: 2.0 },
+        ])
+        .unwrap();
+        trajectory.set_path(Some(path));
+        trajectory.set_shape(Shape::Path);
+        trajectory.set_phase(0.0);
+        trajectory.reset();
+        trajectory.advance(500);
+        let (_, el, dist) = trajectory.position(centre);
+        assert_approx_eq!(el, 20.0, 1e-3);
+        assert_approx_eq!(dist, 1.5, 1e-3);
+    }
+
+    #[test]
+    fn test_fly_by() {
+        let centre = (0.0, 0.0, 1.0);
+        let mut trajectory = Trajectory::new(1000.0);
+        trajectory.set_shape(Shape::FlyBy);
+        trajectory.set_rate_hz(1.0);
+        trajectory.set_width(60.0);
+
+        // starts at one end, passes the centre a quarter cycle later and turns at the other end
+        let (az, el, dist) = trajectory.position(centre);
+        assert_approx_eq!(az, -60.0, 1e-3);
+        assert_eq!(el, 0.0);
+        assert_approx_eq!(dist, 2.0, 1e-3);
+        trajectory.advance(250);
+        let (az, _, dist) = trajectory.position(centre);
+        assert_approx_eq!(az, 0.0, 1e-3);
+        assert_approx_eq!(dist, 1.0, 1e-3);
+        trajectory.advance(250);
+        let (az, _, dist) = trajectory.position(centre);
+        assert_approx_eq!(az, 60.0, 1e-3);
+        assert_approx_eq!(dist, 2.0, 1e-3);
+
+        // the ends stay at a finite distance however wide
+        trajectory.set_width(180.0);
+        assert!(trajectory.position(centre).2 < MAX_DISTANCE);
+    }
+
+    #[test]
+    fn test_path_distance_clamped() {
+        let mut trajectory = Trajectory::new(1000.0);
+        trajectory.set_shape(Shape::Path);
+        for (distance, expected) in [(0.0, MIN_DISTANCE), (100.0, MAX_DISTANCE)] {
+            trajectory.set_path(Some(Automation::fixed(0.0, 0.0, distance)));
+            assert_eq!(trajectory.position((0.0, 0.0, 1.0)).2, expected);
+        }
+    }
+}